@@ -0,0 +1,84 @@
+// Compares the speed of the plain interpreter with the cached one, on a busy
+// loop and on the games in c8games. Run with: cargo bench
+
+use std::path::Path;
+use std::time::Instant;
+
+use chip8_core::{EmuConfig, Emulator, Variant};
+
+// As fast as XO-CHIP games that want the most speed
+const TICKS_PER_FRAME: u32 = 10000;
+const FRAMES: u32 = 600;
+// Every program runs this many times, and the fastest run counts
+const RUNS: usize = 5;
+
+// A loop that never waits: it adds and XORs registers, and draws a sprite
+// every 256 rounds
+const BUSY_LOOP: [u8; 21] = [
+	0xA2, 0x10, // I = 0x210
+	0x70, 0x01, // V0 += 1
+	0x81, 0x04, // V1 += V0
+	0x82, 0x13, // V2 ^= V1
+	0x30, 0x00, // Skip if V0 == 0
+	0x12, 0x02, // Jump to 0x202
+	0xD2, 0x15, // Draw 5 rows at V2, V1
+	0x12, 0x02, // Jump to 0x202
+	0xF0, 0x90, 0x90, 0x90, 0xF0, // Sprite at 0x210
+];
+
+// Run the program and return how many million instructions it ran per
+// second, along with the screen at the end to check that both interpreters
+// agree. Only programs that do not use random numbers (CXNN) draw the same
+// screen every time.
+// The frames are run with tick instead of run_frame, which would skip the
+// rounds of idle loops without running them
+fn run(rom: &[u8], variant: Variant, cached_interpreter: bool) -> (f64, Vec<usize>) {
+	let mut config = EmuConfig::new(variant);
+	config.cached_interpreter = cached_interpreter;
+	let mut emulator = Emulator::new(&config);
+	emulator.load(rom).expect("The ROM fits in RAM");
+
+	let start = Instant::now();
+	for _ in 0..FRAMES {
+		for i in 0..TICKS_PER_FRAME {
+			emulator.tick(i == 0);
+		}
+		emulator.tick_timers();
+	}
+	let time = start.elapsed();
+	let mips = emulator.get_instruction_count() as f64 / time.as_secs_f64() / 1e6;
+	(mips, emulator.get_pixel_indices())
+}
+
+// The fastest of the runs with each interpreter
+fn bench(name: &str, rom: &[u8], variant: Variant, deterministic: bool) {
+	let mut plain_mips: f64 = 0.0;
+	let mut cached_mips: f64 = 0.0;
+	for _ in 0..RUNS {
+		let (mips, plain_screen) = run(rom, variant, false);
+		plain_mips = plain_mips.max(mips);
+		let (mips, cached_screen) = run(rom, variant, true);
+		cached_mips = cached_mips.max(mips);
+		if deterministic {
+			assert!(plain_screen == cached_screen, "{}: the interpreters drew different screens", name);
+		}
+	}
+
+	println!(
+		"{:<10} plain: {:>6.1} MIPS   cached: {:>6.1} MIPS   speedup: {:.2}x",
+		name, plain_mips, cached_mips, cached_mips / plain_mips,
+	);
+}
+
+fn main() {
+	bench("busy loop", &BUSY_LOOP, Variant::XOChip, true);
+
+	// CHIP-8 games, which spend most of their time waiting for the timers,
+	// the keys or the display
+	let games = Path::new(env!("CARGO_MANIFEST_DIR")).join("../c8games");
+	for name in ["BRIX", "INVADERS", "TETRIS", "WIPEOFF"] {
+		if let Ok(rom) = std::fs::read(games.join(name)) {
+			bench(name, &rom, Variant::Chip8, false);
+		}
+	}
+}
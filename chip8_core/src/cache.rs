@@ -0,0 +1,101 @@
+// The cached interpreter: instructions are decoded once, a basic block at a
+// time, and kept by address until the program writes over them
+
+use crate::{EmuConfig, Emulator, Handler};
+
+// Blocks end at the instructions that may not go on to the next one, or
+// after this many instructions
+const MAX_BLOCK_LENGTH: usize = 64;
+
+// A decoded instruction, along with whether the one after it is 4 bytes long
+#[derive(Copy, Clone)]
+pub(crate) struct CachedOp {
+	op: u16,
+	next_opcode_double: bool,
+	handler: Handler,
+}
+
+// The cache has an entry for every address the 16-bit PC can reach, but
+// only with the cached interpreter
+pub(crate) fn code_cache_size(config: &EmuConfig) -> usize {
+	if config.cached_interpreter {
+		config.ram_size.min(1 << 16)
+	} else {
+		0
+	}
+}
+
+impl Emulator {
+	// Fetch and decode the instruction at the PC from the cache, decoding the
+	// block that starts there first if it is not cached
+	pub(crate) fn fetch_cached(&mut self) -> (u16, Handler) {
+		let cached = match self.code_cache.get(self.pc as usize) {
+			Some(Some(cached)) => *cached,
+			_ => self.translate_block(self.pc),
+		};
+		self.pc = self.pc.wrapping_add(2);
+		self.next_opcode_double = cached.next_opcode_double;
+		(cached.op, cached.handler)
+	}
+
+	// Decode the instructions from the address to the end of their basic
+	// block into the cache, and return the first one
+	fn translate_block(&mut self, start: u16) -> CachedOp {
+		let first = self.decode_at(start);
+		let mut cached = first;
+		let mut address = start;
+		for _ in 0..MAX_BLOCK_LENGTH {
+			match self.code_cache.get_mut(address as usize) {
+				Some(entry) => *entry = Some(cached),
+				None => break,
+			}
+			// The PC wraps around after 0xFFFE, and so does the block
+			address = address.wrapping_add(2);
+			let is_cached = matches!(self.code_cache.get(address as usize), Some(Some(_)));
+			if ends_block(cached.op) || is_cached {
+				break;
+			}
+			cached = self.decode_at(address);
+		}
+		first
+	}
+
+	// Decode the instruction at the address, reading RAM the same way fetch
+	// does: the next opcode is at the address the PC moves on to
+	fn decode_at(&self, address: u16) -> CachedOp {
+		let next_address = address.wrapping_add(2) as usize;
+		let address = address as usize;
+		let op = u16::from_be_bytes([self.read_ram(address), self.read_ram(address + 1)]);
+		let next_op = u16::from_be_bytes([self.read_ram(next_address), self.read_ram(next_address + 1)]);
+		CachedOp {
+			op,
+			next_opcode_double: self.is_double_opcode(next_op),
+			handler: self.decode(op),
+		}
+	}
+
+	// Forget the instructions decoded from the bytes that were written. The
+	// instructions up to 3 bytes before them read them as well, to find out
+	// if the next instruction is 4 bytes long
+	pub(crate) fn invalidate_code(&mut self, start: usize, length: usize) {
+		let end = (start + length).min(self.code_cache.len());
+		self.code_cache[start.saturating_sub(3).min(end)..end].fill(None);
+		// The instructions at the end of RAM wrap around to read the first
+		// bytes
+		if start < 3 && self.code_cache.len() == self.ram_size {
+			let cache_size = self.code_cache.len();
+			self.code_cache[cache_size - 3 + start..].fill(None);
+		}
+	}
+
+	// Forget every decoded instruction
+	pub(crate) fn clear_code_cache(&mut self) {
+		self.code_cache.fill(None);
+	}
+}
+
+// True for the instructions that may not go on to the next one: jumps,
+// calls, returns, skips and machine code subroutines
+fn ends_block(op: u16) -> bool {
+	matches!(op & 0xF000, 0x0000 | 0x1000 | 0x2000 | 0x3000 | 0x4000 | 0x5000 | 0x9000 | 0xB000 | 0xE000)
+}
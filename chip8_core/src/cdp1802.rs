@@ -0,0 +1,303 @@
+// The RCA CDP1802, the CPU of the COSMAC VIP. CHIP-8 programs on the VIP can
+// call machine code subroutines with 0NNN, so the CPU shares its memory with
+// the CHIP-8 interpreter.
+//
+// The CPU has 16 registers of 16 bits. Any of them can be the program counter
+// (selected by P) or the pointer used by the arithmetic and memory
+// instructions (selected by X). D is the accumulator, and DF its carry flag.
+
+#[derive(Clone)]
+pub struct Cdp1802 {
+	pub d: u8,
+	pub df: bool,
+	pub r: [u16; 16],
+	pub p: u8,
+	pub x: u8,
+	pub t: u8,
+	pub ie: bool,
+	pub q: bool,
+}
+
+impl Cdp1802 {
+	pub fn new() -> Self {
+		Cdp1802 {
+			d: 0,
+			df: false,
+			r: [0; 16],
+			p: 0,
+			x: 0,
+			t: 0,
+			ie: true,
+			q: false,
+		}
+	}
+
+	// Read a byte from memory. Addresses wrap around the end of the RAM
+	fn read(&self, ram: &[u8], address: u16) -> u8 {
+		ram[address as usize % ram.len()]
+	}
+
+	// Write a byte to memory. Addresses wrap around the end of the RAM
+	fn write(&self, ram: &mut [u8], address: u16, value: u8) {
+		let len = ram.len();
+		ram[address as usize % len] = value;
+	}
+
+	// Read the byte after the instruction and move the program counter past it
+	fn fetch_immediate(&mut self, ram: &[u8]) -> u8 {
+		let value = self.read(ram, self.r[self.p as usize]);
+		self.r[self.p as usize] = self.r[self.p as usize].wrapping_add(1);
+		value
+	}
+
+	// The byte R(X) points to
+	fn read_x(&self, ram: &[u8]) -> u8 {
+		self.read(ram, self.r[self.x as usize])
+	}
+
+	// D = a + b + carry, DF = 1 on overflow
+	fn add(&mut self, a: u8, b: u8, carry: bool) {
+		let sum = a as u16 + b as u16 + carry as u16;
+		self.d = sum as u8;
+		self.df = sum > 0xFF;
+	}
+
+	// D = a - b - borrow, DF = 1 if there was no borrow
+	fn subtract(&mut self, a: u8, b: u8, borrow: bool) {
+		let difference = a as i16 - b as i16 - borrow as i16;
+		self.d = difference as u8;
+		self.df = difference >= 0;
+	}
+
+	// Short branch: jump inside the current page if the condition holds,
+	// otherwise skip the address byte
+	fn short_branch(&mut self, ram: &[u8], condition: bool) {
+		let pc = self.r[self.p as usize];
+		if condition {
+			let low_byte = self.read(ram, pc);
+			self.r[self.p as usize] = (pc & 0xFF00) | low_byte as u16;
+		} else {
+			self.r[self.p as usize] = pc.wrapping_add(1);
+		}
+	}
+
+	// Long branch: jump to the 16-bit address if the condition holds,
+	// otherwise skip the 2 address bytes
+	fn long_branch(&mut self, ram: &[u8], condition: bool) {
+		let pc = self.r[self.p as usize];
+		if condition {
+			let high_byte = self.read(ram, pc) as u16;
+			let low_byte = self.read(ram, pc.wrapping_add(1)) as u16;
+			self.r[self.p as usize] = (high_byte << 8) | low_byte;
+		} else {
+			self.r[self.p as usize] = pc.wrapping_add(2);
+		}
+	}
+
+	// Long skip: skip the next 2 bytes if the condition holds
+	fn long_skip(&mut self, condition: bool) {
+		if condition {
+			self.r[self.p as usize] = self.r[self.p as usize].wrapping_add(2);
+		}
+	}
+
+	// Fetch and execute one instruction, and return how many machine cycles it
+	// took. The external flags EF1-EF4 are never set and the I/O bus reads 0,
+	// since nothing is attached to them
+	pub fn step(&mut self, ram: &mut [u8]) -> u32 {
+		let op = self.fetch_immediate(ram);
+		let i = op >> 4;
+		let n = (op & 0xF) as usize;
+		let x = self.x as usize;
+
+		match (i, n) {
+			// IDL: Wait for an interrupt, which never comes
+			(0x0, 0) => (),
+			// LDN: D = M(R(N))
+			(0x0, _) => self.d = self.read(ram, self.r[n]),
+			// INC: R(N) += 1
+			(0x1, _) => self.r[n] = self.r[n].wrapping_add(1),
+			// DEC: R(N) -= 1
+			(0x2, _) => self.r[n] = self.r[n].wrapping_sub(1),
+
+			// Short branches
+			(0x3, 0x0) => self.short_branch(ram, true),
+			(0x3, 0x1) => self.short_branch(ram, self.q),
+			(0x3, 0x2) => self.short_branch(ram, self.d == 0),
+			(0x3, 0x3) => self.short_branch(ram, self.df),
+			(0x3, 0x4..=0x7) => self.short_branch(ram, false),
+			(0x3, 0x8) => self.short_branch(ram, false),
+			(0x3, 0x9) => self.short_branch(ram, !self.q),
+			(0x3, 0xA) => self.short_branch(ram, self.d != 0),
+			(0x3, 0xB) => self.short_branch(ram, !self.df),
+			(0x3, _) => self.short_branch(ram, true),
+
+			// LDA: D = M(R(N)), R(N) += 1
+			(0x4, _) => {
+				self.d = self.read(ram, self.r[n]);
+				self.r[n] = self.r[n].wrapping_add(1);
+			}
+			// STR: M(R(N)) = D
+			(0x5, _) => self.write(ram, self.r[n], self.d),
+
+			// IRX: R(X) += 1
+			(0x6, 0x0) => self.r[x] = self.r[x].wrapping_add(1),
+			// OUT: Put M(R(X)) on the bus, R(X) += 1
+			(0x6, 0x1..=0x7) => self.r[x] = self.r[x].wrapping_add(1),
+			// Not used on the 1802
+			(0x6, 0x8) => (),
+			// INP: M(R(X)) = D = bus
+			(0x6, _) => {
+				self.d = 0;
+				self.write(ram, self.r[x], self.d);
+			}
+
+			// RET and DIS: X, P = M(R(X)), R(X) += 1, and enable or disable
+			// interrupts
+			(0x7, 0x0) | (0x7, 0x1) => {
+				let value = self.read_x(ram);
+				self.r[x] = self.r[x].wrapping_add(1);
+				self.x = value >> 4;
+				self.p = value & 0xF;
+				self.ie = n == 0;
+			}
+			// LDXA: D = M(R(X)), R(X) += 1
+			(0x7, 0x2) => {
+				self.d = self.read_x(ram);
+				self.r[x] = self.r[x].wrapping_add(1);
+			}
+			// STXD: M(R(X)) = D, R(X) -= 1
+			(0x7, 0x3) => {
+				self.write(ram, self.r[x], self.d);
+				self.r[x] = self.r[x].wrapping_sub(1);
+			}
+			// ADC: D = M(R(X)) + D + DF
+			(0x7, 0x4) => self.add(self.read_x(ram), self.d, self.df),
+			// SDB: D = M(R(X)) - D - !DF
+			(0x7, 0x5) => self.subtract(self.read_x(ram), self.d, !self.df),
+			// SHRC: Shift D right, DF goes into the top bit
+			(0x7, 0x6) => {
+				let lsb = self.d & 1;
+				self.d = (self.d >> 1) | ((self.df as u8) << 7);
+				self.df = lsb == 1;
+			}
+			// SMB: D = D - M(R(X)) - !DF
+			(0x7, 0x7) => self.subtract(self.d, self.read_x(ram), !self.df),
+			// SAV: M(R(X)) = T
+			(0x7, 0x8) => self.write(ram, self.r[x], self.t),
+			// MARK: T = X, P; M(R(2)) = T; X = P; R(2) -= 1
+			(0x7, 0x9) => {
+				self.t = (self.x << 4) | self.p;
+				self.write(ram, self.r[2], self.t);
+				self.x = self.p;
+				self.r[2] = self.r[2].wrapping_sub(1);
+			}
+			// REQ and SEQ: Reset or set Q
+			(0x7, 0xA) => self.q = false,
+			(0x7, 0xB) => self.q = true,
+			// ADCI: D = M(R(P)) + D + DF
+			(0x7, 0xC) => {
+				let value = self.fetch_immediate(ram);
+				self.add(value, self.d, self.df);
+			}
+			// SDBI: D = M(R(P)) - D - !DF
+			(0x7, 0xD) => {
+				let value = self.fetch_immediate(ram);
+				self.subtract(value, self.d, !self.df);
+			}
+			// SHLC: Shift D left, DF goes into the bottom bit
+			(0x7, 0xE) => {
+				let msb = self.d >> 7;
+				self.d = (self.d << 1) | self.df as u8;
+				self.df = msb == 1;
+			}
+			// SMBI: D = D - M(R(P)) - !DF
+			(0x7, _) => {
+				let value = self.fetch_immediate(ram);
+				self.subtract(self.d, value, !self.df);
+			}
+
+			// GLO: D = R(N).0
+			(0x8, _) => self.d = self.r[n] as u8,
+			// GHI: D = R(N).1
+			(0x9, _) => self.d = (self.r[n] >> 8) as u8,
+			// PLO: R(N).0 = D
+			(0xA, _) => self.r[n] = (self.r[n] & 0xFF00) | self.d as u16,
+			// PHI: R(N).1 = D
+			(0xB, _) => self.r[n] = (self.r[n] & 0x00FF) | ((self.d as u16) << 8),
+
+			// Long branches and skips
+			(0xC, 0x0) => self.long_branch(ram, true),
+			(0xC, 0x1) => self.long_branch(ram, self.q),
+			(0xC, 0x2) => self.long_branch(ram, self.d == 0),
+			(0xC, 0x3) => self.long_branch(ram, self.df),
+			// NOP
+			(0xC, 0x4) => (),
+			(0xC, 0x5) => self.long_skip(!self.q),
+			(0xC, 0x6) => self.long_skip(self.d != 0),
+			(0xC, 0x7) => self.long_skip(!self.df),
+			(0xC, 0x8) => self.long_skip(true),
+			(0xC, 0x9) => self.long_branch(ram, !self.q),
+			(0xC, 0xA) => self.long_branch(ram, self.d != 0),
+			(0xC, 0xB) => self.long_branch(ram, !self.df),
+			(0xC, 0xC) => self.long_skip(self.ie),
+			(0xC, 0xD) => self.long_skip(self.q),
+			(0xC, 0xE) => self.long_skip(self.d == 0),
+			(0xC, _) => self.long_skip(self.df),
+
+			// SEP: P = N
+			(0xD, _) => self.p = n as u8,
+			// SEX: X = N
+			(0xE, _) => self.x = n as u8,
+
+			// LDX: D = M(R(X))
+			(0xF, 0x0) => self.d = self.read_x(ram),
+			// OR, AND, XOR: D = M(R(X)) op D
+			(0xF, 0x1) => self.d |= self.read_x(ram),
+			(0xF, 0x2) => self.d &= self.read_x(ram),
+			(0xF, 0x3) => self.d ^= self.read_x(ram),
+			// ADD: D = M(R(X)) + D
+			(0xF, 0x4) => self.add(self.read_x(ram), self.d, false),
+			// SD: D = M(R(X)) - D
+			(0xF, 0x5) => self.subtract(self.read_x(ram), self.d, false),
+			// SHR: Shift D right, the bottom bit goes to DF
+			(0xF, 0x6) => {
+				self.df = self.d & 1 == 1;
+				self.d >>= 1;
+			}
+			// SM: D = D - M(R(X))
+			(0xF, 0x7) => self.subtract(self.d, self.read_x(ram), false),
+			// LDI: D = M(R(P))
+			(0xF, 0x8) => self.d = self.fetch_immediate(ram),
+			// ORI, ANI, XRI: D = M(R(P)) op D
+			(0xF, 0x9) => self.d |= self.fetch_immediate(ram),
+			(0xF, 0xA) => self.d &= self.fetch_immediate(ram),
+			(0xF, 0xB) => self.d ^= self.fetch_immediate(ram),
+			// ADI: D = M(R(P)) + D
+			(0xF, 0xC) => {
+				let value = self.fetch_immediate(ram);
+				self.add(value, self.d, false);
+			}
+			// SDI: D = M(R(P)) - D
+			(0xF, 0xD) => {
+				let value = self.fetch_immediate(ram);
+				self.subtract(value, self.d, false);
+			}
+			// SHL: Shift D left, the top bit goes to DF
+			(0xF, 0xE) => {
+				self.df = self.d >> 7 == 1;
+				self.d <<= 1;
+			}
+			// SMI: D = D - M(R(P))
+			(0xF, _) => {
+				let value = self.fetch_immediate(ram);
+				self.subtract(self.d, value, false);
+			}
+
+			_ => unreachable!(),
+		}
+
+		// Long branches and skips take an extra cycle
+		if i == 0xC {3} else {2}
+	}
+}
@@ -0,0 +1,197 @@
+// The fonts of the different CHIP-8 interpreters. The small font has 16
+// glyphs of 5 bytes (4x5 pixels) used by FX29, the big font has 16 glyphs of
+// 10 bytes (8x10 pixels) used by FX30.
+
+// 80 bytes for the small font
+pub const FONTSET_SIZE: usize = 80;
+// 160 bytes for the big font
+pub const FONTSET_BIG_SIZE: usize = 160;
+
+// Size of the big font of interpreters that only have the digits 0-9
+const FONTSET_BIG_DIGITS_SIZE: usize = 100;
+
+// The fonts that can be used by the emulator
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum FontStyle {
+	Vip,
+	Eti660,
+	Dream6800,
+	SChip10,
+	SChip11,
+	Octo,
+	FishNChips,
+}
+
+impl FontStyle {
+	// Returns the small font
+	pub fn get_font(&self) -> &'static [u8; FONTSET_SIZE] {
+		match self {
+			FontStyle::Vip => &FONTSET_VIP,
+			FontStyle::Eti660 => &FONTSET_ETI660,
+			FontStyle::Dream6800 => &FONTSET_DREAM6800,
+			FontStyle::SChip10 | FontStyle::SChip11 | FontStyle::Octo => &FONTSET_OCTO,
+			FontStyle::FishNChips => &FONTSET_FISHNCHIPS,
+		}
+	}
+
+	// Returns the big font. The SUPER-CHIP big fonts only have the digits
+	// 0-9, so the glyphs for A-F are completed with the ones of Octo. The
+	// interpreters that never had a big font use the one of Octo
+	pub fn get_big_font(&self) -> [u8; FONTSET_BIG_SIZE] {
+		let mut font = FONTSET_BIG_OCTO;
+		match self {
+			FontStyle::SChip10 => font[..FONTSET_BIG_DIGITS_SIZE].copy_from_slice(&FONTSET_BIG_SCHIP10),
+			FontStyle::SChip11 => font[..FONTSET_BIG_DIGITS_SIZE].copy_from_slice(&FONTSET_BIG_SCHIP11),
+			_ => (),
+		}
+		font
+	}
+}
+
+// COSMAC VIP
+const FONTSET_VIP: [u8; FONTSET_SIZE] = [
+	0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+	0x60, 0x20, 0x20, 0x20, 0x70, // 1
+	0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+	0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+	0xA0, 0xA0, 0xF0, 0x20, 0x20, // 4
+	0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+	0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+	0xF0, 0x10, 0x10, 0x10, 0x10, // 7
+	0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+	0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+	0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+	0xF0, 0x50, 0x70, 0x50, 0xF0, // B
+	0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+	0xF0, 0x50, 0x50, 0x50, 0xF0, // D
+	0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+	0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+// ETI-660
+const FONTSET_ETI660: [u8; FONTSET_SIZE] = [
+	0xE0, 0xA0, 0xA0, 0xA0, 0xE0, // 0
+	0x20, 0x20, 0x20, 0x20, 0x20, // 1
+	0xE0, 0x20, 0xE0, 0x80, 0xE0, // 2
+	0xE0, 0x20, 0xE0, 0x20, 0xE0, // 3
+	0xA0, 0xA0, 0xE0, 0x20, 0x20, // 4
+	0xE0, 0x80, 0xE0, 0x20, 0xE0, // 5
+	0xE0, 0x80, 0xE0, 0xA0, 0xE0, // 6
+	0xE0, 0x20, 0x20, 0x20, 0x20, // 7
+	0xE0, 0xA0, 0xE0, 0xA0, 0xE0, // 8
+	0xE0, 0xA0, 0xE0, 0x20, 0xE0, // 9
+	0xE0, 0xA0, 0xE0, 0xA0, 0xA0, // A
+	0xC0, 0xA0, 0xE0, 0xA0, 0xC0, // B
+	0xE0, 0x80, 0x80, 0x80, 0xE0, // C
+	0xC0, 0xA0, 0xA0, 0xA0, 0xC0, // D
+	0xE0, 0x80, 0xE0, 0x80, 0xE0, // E
+	0xE0, 0x80, 0xC0, 0x80, 0x80, // F
+];
+
+// DREAM 6800
+const FONTSET_DREAM6800: [u8; FONTSET_SIZE] = [
+	0xE0, 0xA0, 0xA0, 0xA0, 0xE0, // 0
+	0x40, 0x40, 0x40, 0x40, 0x40, // 1
+	0xE0, 0x20, 0xE0, 0x80, 0xE0, // 2
+	0xE0, 0x20, 0xE0, 0x20, 0xE0, // 3
+	0x80, 0xA0, 0xA0, 0xE0, 0x20, // 4
+	0xE0, 0x80, 0xE0, 0x20, 0xE0, // 5
+	0xE0, 0x80, 0xE0, 0xA0, 0xE0, // 6
+	0xE0, 0x20, 0x20, 0x20, 0x20, // 7
+	0xE0, 0xA0, 0xE0, 0xA0, 0xE0, // 8
+	0xE0, 0xA0, 0xE0, 0x20, 0xE0, // 9
+	0xE0, 0xA0, 0xE0, 0xA0, 0xA0, // A
+	0xC0, 0xA0, 0xE0, 0xA0, 0xC0, // B
+	0xE0, 0x80, 0x80, 0x80, 0xE0, // C
+	0xC0, 0xA0, 0xA0, 0xA0, 0xC0, // D
+	0xE0, 0x80, 0xE0, 0x80, 0xE0, // E
+	0xE0, 0x80, 0xC0, 0x80, 0x80, // F
+];
+
+// CHIP-48, SUPER-CHIP and Octo share the same small font
+const FONTSET_OCTO: [u8; FONTSET_SIZE] = [
+	0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+	0x20, 0x60, 0x20, 0x20, 0x70, // 1
+	0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+	0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+	0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+	0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+	0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+	0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+	0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+	0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+	0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+	0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+	0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+	0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+	0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+	0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+// fish'n'chips
+const FONTSET_FISHNCHIPS: [u8; FONTSET_SIZE] = [
+	0x60, 0xA0, 0xA0, 0xA0, 0xC0, // 0
+	0x40, 0xC0, 0x40, 0x40, 0xE0, // 1
+	0xC0, 0x20, 0x40, 0x80, 0xE0, // 2
+	0xC0, 0x20, 0x40, 0x20, 0xC0, // 3
+	0x20, 0xA0, 0xE0, 0x20, 0x20, // 4
+	0xE0, 0x80, 0xC0, 0x20, 0xC0, // 5
+	0x40, 0x80, 0xC0, 0xA0, 0x40, // 6
+	0xE0, 0x20, 0x60, 0x40, 0x40, // 7
+	0x40, 0xA0, 0x40, 0xA0, 0x40, // 8
+	0x40, 0xA0, 0x60, 0x20, 0x40, // 9
+	0x40, 0xA0, 0xE0, 0xA0, 0xA0, // A
+	0xC0, 0xA0, 0xC0, 0xA0, 0xC0, // B
+	0x60, 0x80, 0x80, 0x80, 0x60, // C
+	0xC0, 0xA0, 0xA0, 0xA0, 0xC0, // D
+	0xE0, 0x80, 0xC0, 0x80, 0xE0, // E
+	0xE0, 0x80, 0xC0, 0x80, 0x80, // F
+];
+
+// SUPER-CHIP 1.0
+const FONTSET_BIG_SCHIP10: [u8; FONTSET_BIG_DIGITS_SIZE] = [
+	0x3C, 0x7E, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0x7E, 0x3C, // 0
+	0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x7E, // 1
+	0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+	0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+	0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+	0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+	0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+	0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+	0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+	0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+];
+
+// SUPER-CHIP 1.1
+const FONTSET_BIG_SCHIP11: [u8; FONTSET_BIG_DIGITS_SIZE] = [
+	0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+	0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+	0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+	0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+	0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+	0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+	0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+	0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+	0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+	0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+];
+
+// Octo
+const FONTSET_BIG_OCTO: [u8; FONTSET_BIG_SIZE] = [
+	0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+	0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+	0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+	0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+	0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+	0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+	0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+	0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+	0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+	0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+	0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+	0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+	0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+	0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+	0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+	0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
@@ -1,57 +1,266 @@
+use std::fmt;
+use std::time::Duration;
+
 use rand::Rng;
 
+mod cache;
+mod cdp1802;
+mod fonts;
+mod render;
+use cache::CachedOp;
+use cdp1802::Cdp1802;
+pub use render::PixelFormat;
+pub use render::RenderError;
+pub use fonts::FontStyle;
+pub use fonts::FONTSET_SIZE;
+pub use fonts::FONTSET_BIG_SIZE;
+
 const RAM_SIZE: usize = 4096;
 const RAM_SIZE_XO: usize = 65536;
+const RAM_SIZE_MEGA: usize = 0x100_0000;
+
+// The timers and the display run at 60 Hz
+const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / 60);
+// run_for drops the frames it is behind by past this, e.g. after the host
+// stalled, instead of running them all at once
+const MAX_FRAMES_BEHIND: u32 = 6;
 
 const NUM_REGISTERS: usize = 16;
+// Default stack depths: 12 on the COSMAC VIP, 16 elsewhere
+const STACK_SIZE_VIP: usize = 12;
 const STACK_SIZE: usize = 16;
 const NUM_KEYS: usize = 16;
 
+// Loading ROMs into RAM starts from this address by default
+pub const START_ADDRESS: u16 = 0x200;
+// Hi-res CHIP-8 programs start with a jump to 0x260, where the VIP kept the
+// hi-res part of the interpreter. The program itself starts at 0x2C0
+const HIRES_START_ADDRESS: u16 = 0x2C0;
+// Machine code subroutines called with 0NNN run until they return with SEP R4.
+// Subroutines that never return are stopped after this many instructions
+const MAX_MACHINE_CODE_STEPS: usize = 1_000_000;
+// The work area of the VIP interpreter at the end of RAM (0xEA0-0xFFF on a
+// VIP with 4K): the stack, the V registers and the display
+const VIP_WORK_AREA_SIZE: usize = 0x160;
+const VIP_STACK_OFFSET: usize = 0x2F;
+const VIP_V_REGISTERS_OFFSET: usize = 0x50;
+const VIP_DISPLAY_OFFSET: usize = 0x60;
+// Timing of the COSMAC VIP: the CPU runs 1.76 MHz / 8 = 220000 machine cycles
+// per second, 3668 of them in every 60 Hz frame. The display takes 1024 of
+// them for its DMA (8 bytes for each of the 128 lines), and the interrupt
+// routine that starts it takes 29 more
+const VIP_CYCLES_PER_FRAME: i32 = 3668;
+const VIP_DISPLAY_CYCLES: i32 = 1024 + 29;
+// Every instruction takes this many cycles for the interpreter to fetch and
+// decode it, on top of its own cost
+const VIP_FETCH_CYCLES: u32 = 40;
+// CHIP-8X programs are loaded after the bigger interpreter
+const CHIP8X_START_ADDRESS: u16 = 0x300;
+// Default locations of the small and big fonts in RAM
+pub const FONT_ADDRESS: u16 = 0x000;
+pub const BIG_FONT_ADDRESS: u16 = 0x050;
+
 pub const PATTERN_BUFFER_SIZE: usize = 16;
 const DEFAULT_PITCH: u8 = 64;
 
-// 80 bytes for the standard font
-const FONTSET_SIZE: usize = 80;
-const FONTSET: [u8; FONTSET_SIZE] = [
-0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-0x20, 0x60, 0x20, 0x20, 0x70, // 1
-0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-0xF0, 0x80, 0xF0, 0x80, 0x80, // F
-];
-
-// 100 bytes for the big font
-const FONTSET_BIG_SIZE: usize = 100;
-const FONTSET_BIG: [u8; FONTSET_BIG_SIZE] = [
-    0x3C, 0x7E, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0x7E, 0x3C, // 0
-    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x7E, // 1
-    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
-    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
-    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
-    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
-    0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
-    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
-    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
-    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
-];
+// The CHIP-8X color board colors the screen in 8 pixel wide zones, one
+// pixel row high
+const CHIP8X_ZONE_WIDTH: usize = 8;
+const CHIP8X_DEFAULT_COLOR: u8 = 1; // Red
+// The sound board plays a tone of 27535 / (port value + 1) Hz
+const CHIP8X_DEFAULT_TONE: u8 = 0x80;
+
+// Resolution of the MEGA-CHIP mode
+pub const MEGA_SCREEN_WIDTH: usize = 256;
+pub const MEGA_SCREEN_HEIGHT: usize = 192;
 
 // Simple enum that shows what variant we should use for the emulation
 #[derive(Copy, Clone, PartialEq)]
 pub enum Variant {
 	Chip8,
+	// The two-page hi-res CHIP-8 on the VIP, with a 64x64 display
+	Chip8HiRes,
+	// CHIP-10, a CHIP-8 with a 128x64 display
+	Chip10,
+	// CHIP-8X on the VIP with the color board, the second keypad and the
+	// sound board
+	Chip8X,
+	// CHIP-48 on the HP-48, the predecessor of the SUPER-CHIP
+	Chip48,
+	// SUPER-CHIP 1.0, without the scroll opcodes
+	SChip10,
+	// SUPER-CHIP 1.1
 	SChip,
 	XOChip,
+	// MEGA-CHIP, a SUPER-CHIP 1.1 with a 256x192 mode of 256 colors
+	MegaChip,
+}
+
+impl Variant {
+	// The size of the RAM on the platform
+	pub fn default_ram_size(&self) -> usize {
+		match self {
+			Variant::Chip8 | Variant::Chip8HiRes | Variant::Chip10 | Variant::Chip8X |
+			Variant::Chip48 | Variant::SChip10 | Variant::SChip => RAM_SIZE,
+			Variant::XOChip => RAM_SIZE_XO,
+			Variant::MegaChip => RAM_SIZE_MEGA,
+		}
+	}
+
+	// The width of the display of the platform, in its highest resolution
+	pub fn screen_width(&self) -> usize {
+		match self {
+			Variant::Chip8 | Variant::Chip8HiRes | Variant::Chip8X | Variant::Chip48 => 64,
+			Variant::Chip10 | Variant::SChip10 | Variant::SChip | Variant::XOChip | Variant::MegaChip => 128,
+		}
+	}
+
+	// The height of the display of the platform, in its highest resolution
+	pub fn screen_height(&self) -> usize {
+		match self {
+			Variant::Chip8 | Variant::Chip8X | Variant::Chip48 => 32,
+			Variant::Chip8HiRes | Variant::Chip10 | Variant::SChip10 | Variant::SChip |
+			Variant::XOChip | Variant::MegaChip => 64,
+		}
+	}
+
+	// The biggest RAM the I register can address on the platform
+	pub fn max_ram_size(&self) -> usize {
+		match self {
+			Variant::MegaChip => RAM_SIZE_MEGA,
+			_ => RAM_SIZE_XO,
+		}
+	}
+
+	// True for the variants that ran on the COSMAC VIP, and can call machine
+	// code subroutines
+	pub fn is_vip(&self) -> bool {
+		matches!(self, Variant::Chip8 | Variant::Chip8HiRes | Variant::Chip10 | Variant::Chip8X)
+	}
+
+	// True for both versions of the SUPER-CHIP, and for the MEGA-CHIP which
+	// behaves like SUPER-CHIP 1.1 outside of its own mode
+	pub fn is_schip(&self) -> bool {
+		matches!(self, Variant::SChip10 | Variant::SChip | Variant::MegaChip)
+	}
+
+	// True if the opcode is an instruction of the platform. The emulator runs
+	// some instructions on platforms that never had them (e.g. SCHIP opcodes
+	// on CHIP-8), this tells them apart
+	pub fn has_opcode(&self, op: u16) -> bool {
+		let x = (op & 0x0F00) >> 8;
+		let schip11 = matches!(self, Variant::SChip | Variant::XOChip | Variant::MegaChip);
+		match op & 0xF000 {
+			0x0000 => match op {
+				0x00E0 | 0x00EE => true,
+				0x00FD..=0x00FF => self.is_schip() || *self == Variant::XOChip,
+				0x00FB | 0x00FC => schip11,
+				_ if op & 0xFFF0 == 0x00C0 => schip11,
+				_ if op & 0xFFF0 == 0x00D0 => *self == Variant::XOChip,
+				// MEGA-CHIP
+				0x0010 | 0x0011 | 0x0700 if *self == Variant::MegaChip => true,
+				_ if op & 0xFFF0 == 0x00B0 && *self == Variant::MegaChip => true,
+				0x0100..=0x09FF if *self == Variant::MegaChip => {
+					let fixed_zero = matches!(op & 0xFF00, 0x0600 | 0x0800) && op & 0xF0 == 0;
+					let uses_nn = !matches!(op & 0xFF00, 0x0600 | 0x0700 | 0x0800);
+					uses_nn || fixed_zero
+				},
+				// 0NNN: Machine code subroutines on the VIP
+				_ => self.is_vip(),
+			},
+			0x5000 => match op & 0xF {
+				0x0 => true,
+				0x1 => *self == Variant::Chip8X,
+				0x2 | 0x3 => *self == Variant::XOChip,
+				_ => false,
+			},
+			0x8000 => matches!(op & 0xF, 0x0..=0x7 | 0xE),
+			0x9000 => op & 0xF == 0,
+			0xE000 => match op & 0xFF {
+				0x9E | 0xA1 => true,
+				0xF2 | 0xF5 => *self == Variant::Chip8X,
+				_ => false,
+			},
+			0xF000 => match op & 0xFF {
+				0x07 | 0x0A | 0x15 | 0x18 | 0x1E | 0x29 | 0x33 | 0x55 | 0x65 => true,
+				0x30 => self.is_schip() || *self == Variant::XOChip,
+				// SCHIP only has 8 flag registers
+				0x75 | 0x85 => {
+					*self == Variant::XOChip || (self.is_schip() && x <= 7)
+				},
+				0x00 | 0x02 => x == 0 && *self == Variant::XOChip,
+				0x01 | 0x3A => *self == Variant::XOChip,
+				0xF8 | 0xFB => *self == Variant::Chip8X,
+				_ => false,
+			},
+			_ => true,
+		}
+	}
+}
+
+// Errors that can happen while loading a ROM
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadError {
+	// The ROM does not fit in RAM
+	TooLarge { size: usize, max_size: usize },
+	// A custom font that is neither a small font, nor a small font followed
+	// by a big font
+	InvalidFont { size: usize },
+}
+
+impl fmt::Display for LoadError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			LoadError::TooLarge { size, max_size } => {
+				write!(f, "ROM is too large: {} bytes (at most {} bytes fit in RAM)", size, max_size)
+			}
+			LoadError::InvalidFont { size } => {
+				write!(f, "Invalid font: {} bytes (expected {} bytes for a small font, optionally followed by 100 or {} bytes for a big font)",
+					   size, FONTSET_SIZE, FONTSET_BIG_SIZE)
+			}
+		}
+	}
+}
+
+impl std::error::Error for LoadError {}
+
+// A digitized sound played by MEGA-CHIP: unsigned 8-bit mono samples
+#[derive(Clone, PartialEq)]
+pub struct MegaSound {
+	pub sample_rate: u16,
+	pub samples: Vec<u8>,
+	pub looping: bool,
+}
+
+// The part of the screen that changed, in pixels of the screen that render
+// draws (the MEGA-CHIP screen in MEGA-CHIP mode)
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DirtyRect {
+	pub x: usize,
+	pub y: usize,
+	pub width: usize,
+	pub height: usize,
+}
+
+impl DirtyRect {
+	// The smallest rectangle that holds both rectangles
+	fn union(&self, other: &DirtyRect) -> DirtyRect {
+		let x = self.x.min(other.x);
+		let y = self.y.min(other.y);
+		let right = (self.x + self.width).max(other.x + other.width);
+		let bottom = (self.y + self.height).max(other.y + other.height);
+		DirtyRect { x, y, width: right - x, height: bottom - y }
+	}
+}
+
+// How MEGA-CHIP mixes the color of a sprite with the color under it
+#[derive(Copy, Clone, PartialEq)]
+enum BlendMode {
+	Normal,
+	Alpha25,
+	Alpha50,
+	Add,
+	Multiply,
 }
 
 // Struct that holds all information about the emulator created
@@ -70,6 +279,165 @@ pub struct EmuConfig {
 	// In the draw opcode, also add the number of
 	// collisions in the bottom border to the V[0xF] register in high_res_mode
 	pub quirk_clipcollision: bool, 
+
+	// FX55 and FX65 increment I by X instead of X+1 (CHIP-48 and SUPER-CHIP
+	// 1.0). Only used together with quirk_memory
+	pub quirk_memoryincrementbyx: bool,
+
+	// FX0A stops waiting when a key is pressed instead of when it is released
+	pub quirk_keypress: bool,
+	// FX1E sets V[0xF] to 1 when I goes past 0xFFF, and to 0 otherwise (the
+	// Amiga interpreter)
+	pub quirk_indexoverflow: bool,
+	// I wraps around at 12 bits (0xFFF) instead of 16 bits
+	pub quirk_index12bit: bool,
+	// DXY0 draws 8x16 sprites in low resolution mode instead of 16x16
+	pub quirk_lowressprite: bool,
+	// 00FE and 00FF clear the screen when they change the resolution
+	pub quirk_resclear: bool,
+	// 8XY6 and 8XYE set V[0xF] before storing the result, so the result wins
+	// when X is 0xF
+	pub quirk_vforder: bool,
+
+	// How many addresses fit in the stack, None for no limit. The program
+	// halts if it overflows (or underflows) the stack
+	pub stack_depth: Option<usize>,
+
+	// Memory layout: where ROMs are loaded, the size of the RAM, and where
+	// the small (FX29) and big (FX30) fonts are stored
+	pub load_address: u16,
+	pub ram_size: usize,
+	pub font_address: u16,
+	pub big_font_address: u16,
+
+	// Which interpreter's fonts to use
+	pub font_style: FontStyle,
+
+	// Run every instruction for as many machine cycles as on the COSMAC VIP,
+	// instead of a fixed number of instructions per frame
+	pub vip_timing: bool,
+
+	// Keep the stack and the display in RAM where the VIP kept them (0xEA0 and
+	// 0xF00), for programs that read or write them directly
+	pub vip_memory_map: bool,
+
+	// Report every instruction that does not exist on the selected variant,
+	// along with its address, to catch programs that depend on extensions
+	pub strict: bool,
+
+	// Decode every instruction once and keep it until the program writes over
+	// it, instead of decoding it every time it runs. Runs the same, faster
+	pub cached_interpreter: bool,
+}
+
+impl EmuConfig {
+	// Returns the config of a platform with the quirks it had
+	pub fn new(variant: Variant) -> Self {
+		let mut config = EmuConfig {
+			variant,
+			quirk_legacyscroll: false,
+			quirk_vfreset: false,
+			quirk_memory: false,
+			quirk_displaywait: false,
+			quirk_clipping: false,
+			quirk_shifting: false,
+			quirk_jumping: false,
+			quirk_clipcollision: false,
+			quirk_memoryincrementbyx: false,
+			quirk_keypress: false,
+			quirk_indexoverflow: false,
+			quirk_index12bit: false,
+			quirk_lowressprite: false,
+			quirk_resclear: false,
+			quirk_vforder: false,
+
+			stack_depth: Some(STACK_SIZE),
+
+			load_address: START_ADDRESS,
+			ram_size: variant.default_ram_size(),
+			font_address: FONT_ADDRESS,
+			big_font_address: BIG_FONT_ADDRESS,
+
+			font_style: FontStyle::Octo,
+
+			vip_timing: false,
+			vip_memory_map: false,
+			strict: false,
+			cached_interpreter: false,
+		};
+		match variant {
+			Variant::Chip8 | Variant::Chip8HiRes | Variant::Chip10 => {
+				config.quirk_vfreset = true;
+				config.quirk_memory = true;
+				config.quirk_clipping = true;
+				config.stack_depth = Some(STACK_SIZE_VIP);
+				config.font_style = FontStyle::Vip;
+			}
+			Variant::Chip8X => {
+				config.quirk_vfreset = true;
+				config.quirk_memory = true;
+				config.quirk_clipping = true;
+				config.stack_depth = Some(STACK_SIZE_VIP);
+				config.font_style = FontStyle::Vip;
+				config.load_address = CHIP8X_START_ADDRESS;
+			}
+			Variant::Chip48 | Variant::SChip10 => {
+				config.quirk_memory = true;
+				config.quirk_memoryincrementbyx = true;
+				config.quirk_clipping = true;
+				config.quirk_shifting = true;
+				config.quirk_jumping = true;
+				// CHIP-48 has no low resolution mode
+				config.quirk_lowressprite = variant == Variant::SChip10;
+				config.font_style = FontStyle::SChip10;
+			}
+			Variant::SChip | Variant::MegaChip => {
+				config.quirk_clipping = true;
+				config.quirk_shifting = true;
+				config.quirk_jumping = true;
+				config.quirk_lowressprite = true;
+				config.font_style = FontStyle::SChip11;
+			}
+			Variant::XOChip => {
+				config.quirk_memory = true;
+				config.quirk_resclear = true;
+			}
+		}
+		config
+	}
+
+	// The biggest ROM that fits in RAM after the load address
+	pub fn max_rom_size(&self) -> usize {
+		// With the VIP memory map, the work area at the end of RAM is not free
+		let free_ram = if self.vip_memory_map {
+			vip_work_area(self.ram_size)
+		} else {
+			self.ram_size
+		};
+		free_ram.saturating_sub(self.load_address as usize)
+	}
+
+	// Check that the memory layout makes sense: the RAM is addressable by
+	// the I register, and the ROM and fonts fit in it
+	pub fn check_memory_layout(&self) -> Result<(), String> {
+		let max_ram_size = self.variant.max_ram_size();
+		if self.ram_size < 512 || self.ram_size > max_ram_size {
+			return Err(format!("RAM size must be between 512 and {} bytes", max_ram_size));
+		}
+		if self.load_address as usize >= self.ram_size {
+			return Err(format!("Load address {:#05X} is outside of RAM", self.load_address));
+		}
+		if self.font_address as usize + FONTSET_SIZE > self.ram_size {
+			return Err(format!("Font address {:#05X} is outside of RAM", self.font_address));
+		}
+		if self.big_font_address as usize + FONTSET_BIG_SIZE > self.ram_size {
+			return Err(format!("Big font address {:#05X} is outside of RAM", self.big_font_address));
+		}
+		if self.vip_memory_map && (self.variant.screen_width() != 64 || self.variant.screen_height() != 32) {
+			return Err("The VIP memory map only fits a 64x32 display".to_string());
+		}
+		Ok(())
+	}
 }
 
 #[derive(Clone)]
@@ -79,13 +447,17 @@ pub struct Emulator {
 	pc: u16,
 	ram_size: usize,
 	ram: Vec<u8>,
-	screen: Vec<Vec<bool>>,
+	// The rows of the 4 planes, with the leftmost pixel in the highest bit
+	screen: Vec<Vec<u128>>,
 	// FYI: v stands for "variable"
 	v_register: [u8; NUM_REGISTERS],
-	// The I register only needs 12 bits, so it's a bit overkill
-	i_register: u16,
-	stack_pointer: i16,
-	stack: [u16; STACK_SIZE],
+	// The I register only needs 12 bits, so it's a bit overkill. It is 32 bits
+	// wide because MEGA-CHIP addresses 24 bits
+	i_register: u32,
+	// The number of entries on the stack, which is also the index of the next
+	// one
+	stack_pointer: usize,
+	stack: Vec<u16>,
 	keys: [bool; NUM_KEYS],
 	previous_frame_keys: [bool; NUM_KEYS],
 	delay_timer: u8,
@@ -94,6 +466,15 @@ pub struct Emulator {
 	screen_width: usize,
 	screen_height: usize,
 	key_frame: bool,
+	halted: bool,
+
+	// With config.strict, the addresses and opcodes of the instructions that
+	// do not exist on the variant, in the order they first ran
+	invalid_opcodes: Vec<(u16, u16)>,
+
+	// The fonts that get copied to RAM
+	font: [u8; FONTSET_SIZE],
+	big_font: [u8; FONTSET_BIG_SIZE],
 
 	// Needed for the SChip variants	
 	high_res_mode: bool,
@@ -105,39 +486,79 @@ pub struct Emulator {
 	num_planes: u8,
 	pub pattern_buffer: [u8; PATTERN_BUFFER_SIZE],
 	pub pitch: u8,
-}
 
-// Loading ROMs into RAM starts from this address
-const START_ADDRESS: u16 = 0x200;
+	// Needed for the VIP variants, to run machine code subroutines
+	cpu: Cdp1802,
+	// Needed for the VIP timing: the cycles left in the current frame, and the
+	// cycles the last instruction took
+	cycle_budget: i32,
+	instruction_cycles: u32,
+
+	// Needed for run_for: the time passed that was not enough for a frame,
+	// and the instructions per second that did not add up to a whole tick
+	frame_time: Duration,
+	tick_remainder: u32,
+	// How many instructions ran since the last reset
+	instruction_count: u64,
+
+	// Needed for the idle loop detection: the PC, V and I at the start of a
+	// loop without side effects, the ticks and cycles it took since, and the
+	// length of the loop once it comes back to the same state
+	idle_loop: Option<(u16, [u8; NUM_REGISTERS], u32)>,
+	idle_loop_ticks: u32,
+	idle_loop_cycles: u32,
+	idle_period: Option<(u32, u32)>,
+	idle: bool,
+	// The part of the screen that changed since the last take_dirty
+	dirty: Option<DirtyRect>,
+	// The decoded instructions of the cached interpreter, by address
+	code_cache: Vec<Option<CachedOp>>,
+
+	// Needed for the Chip8X variant: the foreground color of every zone, the
+	// background color, the second keypad, and the I/O port of the sound board
+	color_map: Vec<u8>,
+	background_color: u8,
+	keys_2: [bool; NUM_KEYS],
+	port_output: u8,
+	port_input: u8,
+
+	// Needed for the MegaChip variant. The screen keeps the palette index of
+	// every pixel for collisions, and the blended ARGB colors that are shown
+	// once the frame is finished with 00E0
+	mega_mode: bool,
+	mega_indices: Vec<u8>,
+	mega_frame: Vec<u32>,
+	mega_display: Vec<u32>,
+	mega_palette: [u32; 256],
+	sprite_width: usize,
+	sprite_height: usize,
+	blend_mode: BlendMode,
+	collision_color: u8,
+	mega_sound: Option<MegaSound>,
+}
 
 impl Emulator {
 	pub fn new(given_config: &EmuConfig) -> Self {
-		let width = match given_config.variant {
-			Variant::Chip8 => 64,
-			Variant::SChip => 128,
-			Variant::XOChip => 128,
-		};
-		let height = match given_config.variant {
-			Variant::Chip8 => 32,
-			Variant::SChip => 64,
-			Variant::XOChip => 64,
-		};
-		let platform_ram_size = match given_config.variant {
-			Variant::Chip8 | Variant::SChip => RAM_SIZE,
-			Variant::XOChip => RAM_SIZE_XO,
+		let width = given_config.variant.screen_width();
+		let height = given_config.variant.screen_height();
+		let platform_ram_size = given_config.ram_size;
+		let mega_size = if given_config.variant == Variant::MegaChip {
+			MEGA_SCREEN_WIDTH * MEGA_SCREEN_HEIGHT
+		} else {
+			0
 		};
 		
 		let mut new_emulator = Self {
 			config: given_config.clone(),
 			
-			pc: START_ADDRESS,
+			pc: given_config.load_address,
 			ram_size: platform_ram_size,
 			ram: vec![0; platform_ram_size],
-			screen: vec![vec![false; width * height]; 4],
+			screen: vec![vec![0; height]; 4],
 			v_register: [0; NUM_REGISTERS],
 			i_register: 0,
-			stack_pointer: -1,
-			stack: [0; STACK_SIZE],
+			stack_pointer: 0,
+			stack: Vec::new(),
 			keys: [false; NUM_KEYS],
 			previous_frame_keys: [false; NUM_KEYS],
 			delay_timer: 0,
@@ -146,6 +567,11 @@ impl Emulator {
 			screen_width: width,
 			screen_height: height,
 			key_frame: true,
+			halted: false,
+			invalid_opcodes: Vec::new(),
+
+			font: *given_config.font_style.get_font(),
+			big_font: given_config.font_style.get_big_font(),
 
 			high_res_mode: false,
 			rpl: [0; 16],
@@ -155,15 +581,70 @@ impl Emulator {
 			num_planes: 4,
 			pattern_buffer: [0; PATTERN_BUFFER_SIZE],
 			pitch: DEFAULT_PITCH,
+
+			cpu: Cdp1802::new(),
+			cycle_budget: 0,
+			instruction_cycles: 0,
+
+			frame_time: Duration::ZERO,
+			tick_remainder: 0,
+			instruction_count: 0,
+
+			idle_loop: None,
+			idle_loop_ticks: 0,
+			idle_loop_cycles: 0,
+			idle_period: None,
+			idle: false,
+			dirty: Some(DirtyRect { x: 0, y: 0, width, height }),
+			code_cache: vec![None; cache::code_cache_size(given_config)],
+
+			color_map: vec![CHIP8X_DEFAULT_COLOR; (width / CHIP8X_ZONE_WIDTH) * height],
+			background_color: 0,
+			keys_2: [false; NUM_KEYS],
+			port_output: CHIP8X_DEFAULT_TONE,
+			port_input: 0,
+
+			mega_mode: false,
+			mega_indices: vec![0; mega_size],
+			mega_frame: vec![0; mega_size],
+			mega_display: vec![0; mega_size],
+			mega_palette: default_mega_palette(),
+			sprite_width: 0,
+			sprite_height: 0,
+			blend_mode: BlendMode::Normal,
+			collision_color: 0,
+			mega_sound: None,
 		};
 
-		new_emulator.ram[..FONTSET_SIZE].copy_from_slice(&FONTSET);
-		new_emulator.ram[FONTSET_SIZE..FONTSET_SIZE+FONTSET_BIG_SIZE].copy_from_slice(
-			&FONTSET_BIG
-		);
+		new_emulator.load_fonts();
 		new_emulator
 	}
 
+	// Copy the fonts to their addresses in RAM
+	fn load_fonts(&mut self) {
+		let font_address = self.config.font_address as usize;
+		let big_font_address = self.config.big_font_address as usize;
+		self.ram[font_address..font_address+FONTSET_SIZE].copy_from_slice(&self.font);
+		self.ram[big_font_address..big_font_address+FONTSET_BIG_SIZE].copy_from_slice(
+			&self.big_font
+		);
+	}
+
+	// Replace the fonts with a custom one: a small font (80 bytes), followed
+	// by an optional big font with either the digits 0-9 (100 bytes) or all 16
+	// glyphs (160 bytes). Big glyphs that are not given are kept. The custom
+	// font is kept on reset
+	pub fn load_font(&mut self, data: &[u8]) -> Result<(), LoadError> {
+		let big_font_size = data.len().wrapping_sub(FONTSET_SIZE);
+		if data.len() < FONTSET_SIZE || ![0, 100, FONTSET_BIG_SIZE].contains(&big_font_size) {
+			return Err(LoadError::InvalidFont { size: data.len() });
+		}
+		self.font.copy_from_slice(&data[..FONTSET_SIZE]);
+		self.big_font[..big_font_size].copy_from_slice(&data[FONTSET_SIZE..]);
+		self.load_fonts();
+		Ok(())
+	}
+
 	// Decrement the delay and sound timers by 1
 	pub fn tick_timers(&mut self) {
 		// Copy the keys state of the previous frame
@@ -184,28 +665,21 @@ impl Emulator {
 
 	// Resets all fields (you'll probably need to reload the ROM as well)
 	pub fn reset(&mut self) {
-		let mut matrix: Vec<Vec<bool>> = vec![];
-		for _ in 0..4 {
-			let row: Vec<bool> = vec![false; self.screen_width*self.screen_height];
-			matrix.push(row);
-		}
-		
-		self.pc = START_ADDRESS;
+		self.pc = self.config.load_address;
 		self.ram = vec![0; self.ram_size];
-		self.screen = matrix;
+		self.clear_code_cache();
+		self.screen = vec![vec![0; self.screen_height]; 4];
 		self.v_register = [0; NUM_REGISTERS];
 		self.i_register = 0;
-		self.stack_pointer = -1;
-		self.stack = [0; STACK_SIZE];
+		self.stack_pointer = 0;
+		self.stack = Vec::new();
 		self.keys = [false; NUM_KEYS];
 		self.previous_frame_keys = [false; NUM_KEYS];
 		self.delay_timer = 0;
 		self.sound_timer = 0;
-		self.ram[..FONTSET_SIZE].copy_from_slice(&FONTSET);
-		self.ram[FONTSET_SIZE..FONTSET_SIZE+FONTSET_BIG_SIZE].copy_from_slice(
-			&FONTSET_BIG
-		);
+		self.load_fonts();
 		self.key_frame = true;
+		self.halted = false;
 
 		self.high_res_mode = false;
 
@@ -213,67 +687,522 @@ impl Emulator {
 		self.selected_planes = 1;
 		self.pattern_buffer = [0; PATTERN_BUFFER_SIZE];
 		self.pitch = DEFAULT_PITCH;
+
+		self.cpu = Cdp1802::new();
+		self.cycle_budget = 0;
+		self.instruction_cycles = 0;
+
+		self.frame_time = Duration::ZERO;
+		self.tick_remainder = 0;
+		self.instruction_count = 0;
+
+		self.idle_loop = None;
+		self.idle_period = None;
+		self.idle = false;
+
+		self.color_map.fill(CHIP8X_DEFAULT_COLOR);
+		self.background_color = 0;
+		self.keys_2 = [false; NUM_KEYS];
+		self.port_output = CHIP8X_DEFAULT_TONE;
+
+		self.mega_mode = false;
+		self.clear_mega_screen();
+		self.mega_display.fill(0);
+		self.mega_palette = default_mega_palette();
+		self.sprite_width = 0;
+		self.sprite_height = 0;
+		self.blend_mode = BlendMode::Normal;
+		self.collision_color = 0;
+		self.mega_sound = None;
+		self.mark_screen_dirty();
 	}
 	
 	pub fn tick(&mut self, key_frame: bool) {
+		if self.halted {
+			return;
+		}
 		self.key_frame = key_frame;
-		// Fetch
-		let op = self.fetch();
-		// Decode and Execute
-		self.execute(op);
+		self.instruction_count += 1;
+		// Fetch and decode
+		let (op, handler) = if self.config.cached_interpreter {
+			self.fetch_cached()
+		} else {
+			let op = self.fetch();
+			(op, self.decode(op))
+		};
+		if self.config.strict {
+			self.check_opcode(op);
+		}
+		self.instruction_cycles = if self.config.vip_timing {
+			self.vip_cycles(op)
+		} else {
+			0
+		};
+		// Execute
+		let pc = self.pc.wrapping_sub(2);
+		handler(self, op);
+		if self.config.vip_memory_map {
+			self.sync_vip_display(op);
+		}
+		self.track_idle_loop(op, pc);
+	}
+
+	// Run the instructions of one 60 Hz frame: ticks_per_frame of them, or
+	// with the VIP timing, as many as fit in the cycles the VIP has left after
+	// drawing the display. An instruction that waits (for the display, a key,
+	// or a jump to itself) ends the frame early, as it would spend the rest
+	// of it waiting
+	pub fn run_frame(&mut self, ticks_per_frame: u32) {
+		// The timers and the keys may have changed, so loops from the last
+		// frame may not repeat anymore
+		self.idle_loop = None;
+		self.idle = false;
+		if !self.config.vip_timing {
+			let mut i = 0;
+			while i < ticks_per_frame {
+				// The first tick on each frame is a "key" frame
+				// This is useful for display wait
+				self.tick(i == 0);
+				i += 1;
+				// An idle loop would run the same way until the end of the
+				// frame, so the whole rounds of it left are skipped
+				if let Some((ticks, _)) = self.idle_period.take() {
+					let skipped = (ticks_per_frame - i) / ticks * ticks;
+					i += skipped;
+					self.instruction_count += skipped as u64;
+					self.idle = true;
+				}
+			}
+			return;
+		}
+
+		self.cycle_budget += VIP_CYCLES_PER_FRAME - VIP_DISPLAY_CYCLES;
+		let mut key_frame = true;
+		while self.cycle_budget > 0 && !self.halted {
+			let pc = self.pc;
+			self.tick(key_frame);
+			key_frame = false;
+			self.cycle_budget -= self.instruction_cycles as i32;
+			if self.pc == pc {
+				self.cycle_budget = 0;
+				self.idle = true;
+			}
+			if let Some((ticks, cycles)) = self.idle_period.take() {
+				let rounds = self.cycle_budget.max(0) as u32 / cycles.max(1);
+				self.cycle_budget -= (rounds * cycles) as i32;
+				self.instruction_count += (rounds * ticks) as u64;
+				self.idle = true;
+			}
+		}
 	}
 
-	// Return the 2 screen buffers
-	pub fn get_screen_buffers(&self) -> &Vec<Vec<bool>>{
+	// True if the last frame ended in a loop that only waits for the next
+	// one, e.g. for the delay timer or for a key with FX0A. The host can
+	// sleep until the next frame
+	pub fn is_idle(&self) -> bool {
+		self.idle
+	}
+
+	// The part of the screen that changed since the last call, or None if
+	// nothing did. Frontends can skip drawing static screens with this
+	pub fn take_dirty(&mut self) -> Option<DirtyRect> {
+		self.dirty.take()
+	}
+
+	// Look for loops that come back to the same PC, V and I without doing
+	// anything else. Until the next frame changes the timers or the keys,
+	// such a loop runs the same way forever
+	fn track_idle_loop(&mut self, op: u16, pc: u16) {
+		if !self.has_no_side_effects(op) {
+			self.idle_loop = None;
+			return;
+		}
+		self.idle_loop_ticks += 1;
+		self.idle_loop_cycles += self.instruction_cycles;
+		// Only jumps back can close a loop
+		if self.pc > pc {
+			return;
+		}
+		let state = (self.pc, self.v_register, self.i_register);
+		if self.idle_loop == Some(state) {
+			self.idle_period = Some((self.idle_loop_ticks, self.idle_loop_cycles));
+		} else {
+			self.idle_loop = Some(state);
+		}
+		self.idle_loop_ticks = 0;
+		self.idle_loop_cycles = 0;
+	}
+
+	// True for the instructions that only change the PC, V and I, and only
+	// read what stays the same during a frame
+	fn has_no_side_effects(&self, op: u16) -> bool {
+		match op & 0xF000 {
+			0x1000 | 0x3000 | 0x4000 | 0x6000 | 0x7000 | 0x8000 | 0x9000 | 0xA000 => true,
+			0x5000 => op & 0xF == 0 || (op & 0xF == 3 && self.config.variant == Variant::XOChip),
+			0xB000 => !self.is_chip8x(),
+			0xE000 => matches!(op & 0xFF, 0x9E | 0xA1),
+			0xF000 => op != 0xF000 && matches!(op & 0xFF, 0x07 | 0x0A | 0x1E | 0x29 | 0x30 | 0x65),
+			_ => false,
+		}
+	}
+
+	// Run one frame and then tick the timers, as the VIP did on the vertical
+	// blank interrupt
+	pub fn run_until_vblank(&mut self, ticks_per_frame: u32) {
+		self.run_frame(ticks_per_frame);
+		self.tick_timers();
+	}
+
+	// Run the frames that fit in the time that passed, at 60 frames per
+	// second no matter how often it is called, and instructions_per_second
+	// spread over them (ignored with the VIP timing). The time left over is
+	// kept for the next call. Returns how many frames were run, so the screen
+	// only needs to be drawn when it is not 0
+	pub fn run_for(&mut self, duration: Duration, instructions_per_second: u32) -> u32 {
+		self.frame_time += duration;
+		let mut frames = 0;
+		while self.frame_time >= FRAME_DURATION {
+			self.frame_time -= FRAME_DURATION;
+			if frames == MAX_FRAMES_BEHIND {
+				self.frame_time = Duration::ZERO;
+				break;
+			}
+			let ticks = instructions_per_second + self.tick_remainder;
+			self.tick_remainder = ticks % 60;
+			self.run_until_vblank(ticks / 60);
+			frames += 1;
+		}
+		frames
+	}
+
+	// How many instructions ran since the last reset, to measure the speed
+	pub fn get_instruction_count(&self) -> u64 {
+		self.instruction_count
+	}
+
+	// Switch the VIP timing on or off while running
+	pub fn set_vip_timing(&mut self, vip_timing: bool) {
+		self.config.vip_timing = vip_timing;
+		self.cycle_budget = 0;
+	}
+
+	// The machine cycles an instruction takes on the VIP interpreter, measured
+	// from its code. Skips that are taken cost 4 more cycles, which is added
+	// once the instruction ran
+	fn vip_cycles(&self, op: u16) -> u32 {
+		let x = ((op & 0x0F00) >> 8) as usize;
+		let n = (op & 0x000F) as u32;
+		let cost = match op & 0xF000 {
+			0x0000 => match op {
+				0x00E0 => 24 + 3078,
+				0x00EE => 10,
+				// Plus the cycles of the machine code, counted when it runs
+				_ => 4,
+			},
+			0x1000 => 12,
+			0x2000 => 26,
+			0x3000 | 0x4000 => 10,
+			0x5000 | 0x9000 => 14,
+			0x6000 => 6,
+			0x7000 => 10,
+			0x8000 => 44,
+			0xA000 => 12,
+			0xB000 => 22,
+			0xC000 => 36,
+			0xD000 => {
+				// Sprites that are not aligned to a byte cost more per row
+				let rows = if n == 0 {16} else {n};
+				let aligned = self.v_register[x] & 7 == 0;
+				68 + rows * (46 + if aligned {0} else {20})
+			}
+			0xE000 => 14,
+			_ => match op & 0x00FF {
+				0x07 | 0x15 | 0x18 => 10,
+				0x0A => 18,
+				0x1E | 0x29 => 16,
+				0x33 => {
+					let value = self.v_register[x] as u32;
+					84 + 16 * (value / 100 + (value / 10) % 10 + value % 10)
+				}
+				0x55 | 0x65 => 14 + 14 * (x as u32 + 1),
+				_ => 10,
+			},
+		};
+		VIP_FETCH_CYCLES + cost
+	}
+
+	// Taken skips on the VIP cost 4 more cycles
+	fn skip_cycles(&mut self) {
+		self.instruction_cycles += 4;
+	}
+
+	// Remember an instruction that does not exist on the variant, once for
+	// each address
+	fn check_opcode(&mut self, op: u16) {
+		if !self.config.variant.has_opcode(op) {
+			self.report_opcode(op);
+		}
+	}
+
+	// Remember the instruction that is running as invalid, unless it already
+	// is
+	fn report_opcode(&mut self, op: u16) {
+		let address = self.pc.wrapping_sub(2);
+		if !self.invalid_opcodes.iter().any(|&(a, _)| a == address) {
+			self.invalid_opcodes.push((address, op));
+		}
+	}
+
+	// With config.strict, the (address, opcode) of every instruction that ran
+	// and does not exist on the variant. The report is kept after a reset, as
+	// it describes the ROM
+	pub fn get_invalid_opcodes(&self) -> &[(u16, u16)] {
+		&self.invalid_opcodes
+	}
+
+	// True if the program exited with 00FD, overflowed its stack, or ran an
+	// instruction the variant does not decode with config.strict
+	pub fn is_halted(&self) -> bool {
+		self.halted
+	}
+
+	// The address of the next instruction
+	pub fn get_pc(&self) -> u16 {
+		self.pc
+	}
+
+	// The registers and the RAM, to look at the state of a program from the
+	// outside, e.g. in a debugger or a test
+	pub fn get_i_register(&self) -> u32 {
+		self.i_register
+	}
+
+	pub fn get_v_registers(&self) -> &[u8] {
+		&self.v_register
+	}
+
+	pub fn get_ram(&self) -> &[u8] {
+		&self.ram
+	}
+
+	// The width of the screen buffers
+	pub fn get_screen_width(&self) -> usize {
+		self.screen_width
+	}
+
+	// The height of the screen buffers
+	pub fn get_screen_height(&self) -> usize {
+		self.screen_height
+	}
+
+	// Return the rows of the 4 planes. Every row is a u128 with the leftmost
+	// pixel in the highest bit, and only the get_screen_width highest bits used
+	pub fn get_screen_planes(&self) -> &Vec<Vec<u128>> {
 		&self.screen
 	}
 
+	// True if the pixel is on in the given plane
+	pub fn get_pixel(&self, plane_index: usize, x: usize, y: usize) -> bool {
+		self.screen[plane_index][y] & (1 << (127 - x)) != 0
+	}
+
+	// The color index of a pixel: bit i is set if the pixel is on in plane i
+	pub fn get_pixel_index(&self, x: usize, y: usize) -> usize {
+		(0..self.screen.len()).filter(|&i| self.get_pixel(i, x, y)).map(|i| 1 << i).sum()
+	}
+
+	// The color index of every pixel, row by row
+	pub fn get_pixel_indices(&self) -> Vec<usize> {
+		let mut indices = Vec::with_capacity(self.screen_width * self.screen_height);
+		for y in 0..self.screen_height {
+			for x in 0..self.screen_width {
+				let mut index = 0;
+				for (i, plane) in self.screen.iter().enumerate() {
+					index |= ((plane[y] >> (127 - x)) as usize & 1) << i;
+				}
+				indices.push(index);
+			}
+		}
+		indices
+	}
+
+	// True while MEGA-CHIP mode is on. The screen to show is then the one from
+	// get_mega_screen instead of the screen buffers
+	pub fn is_mega_mode(&self) -> bool {
+		self.mega_mode
+	}
+
+	// Return the last finished MEGA-CHIP frame as ARGB colors
+	pub fn get_mega_screen(&self) -> &Vec<u32> {
+		&self.mega_display
+	}
+
+	// Return the digitized sound MEGA-CHIP is playing, if any
+	pub fn get_mega_sound(&self) -> Option<&MegaSound> {
+		self.mega_sound.as_ref()
+	}
+
 	pub fn register_keypress(&mut self, index: usize, pressed: bool) {
 		self.keys[index] = pressed;
 	}
 
-	// Loads a ROM into ram starting from START_ADDRESS
-	pub fn load(&mut self, data: &[u8]) {
-		let start = START_ADDRESS as usize;
+	// Register a key of the second keypad of the CHIP-8X
+	pub fn register_keypress_2(&mut self, index: usize, pressed: bool) {
+		self.keys_2[index] = pressed;
+	}
+
+	// Set the value the CHIP-8X reads from its input port with FXFB
+	pub fn set_port_input(&mut self, value: u8) {
+		self.port_input = value;
+	}
+
+	// Return the CHIP-8X color of every pixel: the background color (0-3:
+	// blue, black, green, red) for pixels that are off, or 8 + the
+	// foreground color of the zone (0-7: black, red, blue, violet, green,
+	// yellow, aqua, white) for pixels that are on
+	pub fn get_color_indices(&self) -> Vec<usize> {
+		let zones_per_row = self.screen_width / CHIP8X_ZONE_WIDTH;
+		(0..self.screen_width * self.screen_height).map(|index| {
+			let (x, y) = (index % self.screen_width, index / self.screen_width);
+			if self.get_pixel(0, x, y) {
+				let zone = x / CHIP8X_ZONE_WIDTH + zones_per_row * y;
+				8 + self.color_map[zone] as usize
+			} else {
+				self.background_color as usize
+			}
+		}).collect()
+	}
+
+	// Loads a ROM into ram starting from the load address. Fails if the ROM
+	// does not fit in RAM
+	pub fn load(&mut self, data: &[u8]) -> Result<(), LoadError> {
+		let max_size = self.config.max_rom_size();
+		if data.len() > max_size {
+			return Err(LoadError::TooLarge { size: data.len(), max_size });
+		}
+		let start = self.config.load_address as usize;
 		let end = start + data.len();
 		self.ram[start..end].copy_from_slice(data);
+		self.invalidate_code(start, data.len());
+		if self.config.variant == Variant::Chip8HiRes && is_hires_rom(data) {
+			self.pc = HIRES_START_ADDRESS;
+		}
+		Ok(())
 	}
 
-	// Push a value to the stack
+	// Push a value to the stack. Overflowing the stack halts the program
 	fn push(&mut self, value: u16) {
+		if let Some(depth) = self.config.stack_depth {
+			if self.stack_pointer >= depth {
+				self.halted = true;
+				return;
+			}
+		}
+		if self.config.vip_memory_map {
+			let address = self.vip_stack_address(self.stack_pointer);
+			self.write_ram(address, value as u8);
+			self.write_ram(address + self.ram_size - 1, (value >> 8) as u8);
+		} else {
+			self.stack.truncate(self.stack_pointer);
+			self.stack.push(value);
+		}
 		self.stack_pointer += 1;
-		self.stack[self.stack_pointer as usize] = value;
 	}		
 
-	// Pop a value from the stack
+	// Pop a value from the stack. Returning with an empty stack halts the
+	// program
 	fn pop(&mut self) -> u16 {
+		if self.stack_pointer == 0 {
+			self.halted = true;
+			return self.pc;
+		}
 		self.stack_pointer -= 1;
-		self.stack[(self.stack_pointer + 1) as usize]
+		if self.config.vip_memory_map {
+			let address = self.vip_stack_address(self.stack_pointer);
+			let low_byte = self.read_ram(address) as u16;
+			let high_byte = self.read_ram(address + self.ram_size - 1) as u16;
+			(high_byte << 8) | low_byte
+		} else {
+			self.stack[self.stack_pointer]
+		}
+	}
+
+	// Read a byte of RAM. Addresses past the end of RAM wrap around to the
+	// start, so programs cannot read outside of it
+	fn read_ram(&self, address: usize) -> u8 {
+		self.ram[address % self.ram_size]
+	}
+
+	// Write a byte of RAM, wrapping around like read_ram, and forget the
+	// instructions decoded from it
+	fn write_ram(&mut self, address: usize, value: u8) {
+		let address = address % self.ram_size;
+		self.ram[address] = value;
+		self.invalidate_code(address, 1);
+	}
+
+	// With the VIP memory map, the stack grows down from 0xECF. Every entry
+	// takes 2 bytes: the low byte, and the high byte below it. Returns the
+	// address of the low byte of an entry
+	fn vip_stack_address(&self, entry: usize) -> usize {
+		let top = (self.vip_work_area() + VIP_STACK_OFFSET) as isize;
+		(top - 2 * entry as isize).rem_euclid(self.ram_size as isize) as usize
+	}
+
+	// With the VIP memory map, the display lives in RAM at 0xF00, one bit per
+	// pixel with 8 bytes per row. Instructions that draw update the RAM, and
+	// instructions that write to RAM (which may be the display) update the
+	// screen
+	fn sync_vip_display(&mut self, op: u16) {
+		let draws = op == 0x00E0 || (op & 0xF000) == 0xD000;
+		let calls_machine_code = (op & 0xF000) == 0x0000 && op != 0x0000 && op != 0x00EE;
+		let stores = (op & 0xF0FF) == 0xF055 || (op & 0xF0FF) == 0xF033;
+		let display_address = self.vip_work_area() + VIP_DISPLAY_OFFSET;
+		let display_size = self.screen_width * self.screen_height / 8;
+		if draws {
+			for byte in 0..display_size {
+				let (x, y) = (byte * 8 % self.screen_width, byte * 8 / self.screen_width);
+				self.ram[display_address + byte] = (self.screen[0][y] >> (120 - x)) as u8;
+			}
+			self.invalidate_code(display_address, display_size);
+		} else if calls_machine_code || stores {
+			for byte in 0..display_size {
+				let (x, y) = (byte * 8 % self.screen_width, byte * 8 / self.screen_width);
+				let value = self.ram[display_address + byte] as u128;
+				let old_row = self.screen[0][y];
+				let row = (old_row & !(0xFF << (120 - x))) | (value << (120 - x));
+				self.screen[0][y] = row;
+				self.mark_row_dirty(y, old_row ^ row);
+			}
+		}
 	}
 
 	// Get and return the next opcode according to the PC
 	fn fetch(&mut self) -> u16 {
-		let higher_byte = self.ram[self.pc as usize] as u16;
-		let lower_byte = self.ram[(self.pc + 1) as usize] as u16;
+		let higher_byte = self.read_ram(self.pc as usize) as u16;
+		let lower_byte = self.read_ram(self.pc as usize + 1) as u16;
 		let op = (higher_byte << 8) | lower_byte;
-		self.pc += 2;
+		self.pc = self.pc.wrapping_add(2);
 		// Look at the next opcode to check if it is 4 bytes long
 		// and update the next_opcode_double flag
-		let higher_byte = self.ram[self.pc as usize] as u16;
-		let lower_byte = self.ram[(self.pc + 1) as usize] as u16;
+		let higher_byte = self.read_ram(self.pc as usize) as u16;
+		let lower_byte = self.read_ram(self.pc as usize + 1) as u16;
 		let op2 = (higher_byte << 8) | lower_byte;
-		if op2 == 0xF000 {
-			self.next_opcode_double = true;
-		}
-		else {
-			self.next_opcode_double = false;
-		}
+		self.next_opcode_double = self.is_double_opcode(op2);
 		op
 	}
 
+	// True for the opcodes that are 4 bytes long
+	fn is_double_opcode(&self, op: u16) -> bool {
+		// MEGA-CHIP's 01NN NNNN is 4 bytes long as well
+		let is_long_mega_op = self.config.variant == Variant::MegaChip && (op & 0xFF00) == 0x0100;
+		op == 0xF000 || is_long_mega_op
+	}
+
 
-	// Read to opcode and execute the relative command
-	fn execute(&mut self, op: u16) {
+	// Find the function that runs an opcode. Which one it is only depends on
+	// the opcode and the variant, so the cached interpreter can keep it
+	fn decode(&self, op: u16) -> Handler {
 		// Big-endian system, so the first we digit we read is digit1
 		let digit1 = ((op & 0xF000) >> 12) as u8;
 		let digit2 = ((op & 0x0F00) >> 8) as u8;
@@ -284,185 +1213,303 @@ impl Emulator {
 
 		match (digit1, digit2, digit3, digit4) {
 			// 0000: Nop
-			(0x0, 0x0, 0x0, 0x0) => self.opcode_0000(),
+			(0x0, 0x0, 0x0, 0x0) => |emu, _| emu.opcode_0000(),
 			
 			// 00E0: Clear screen
-			(0x0, 0x0, 0xE, 0x0) => self.opcode_00e0(),
+			(0x0, 0x0, 0xE, 0x0) => |emu, _| emu.opcode_00e0(),
 			
 			// 00EE: Return from subroutine
-			(0x0, 0x0, 0xE, 0xE) => self.opcode_00ee(),
+			(0x0, 0x0, 0xE, 0xE) => |emu, _| emu.opcode_00ee(),
 			
 			// 1MMM: Jump
-			(0x1, _, _, _) => self.opcode_1mmm(op & 0xFFF),
+			(0x1, _, _, _) => |emu, op| emu.opcode_1mmm(op & 0xFFF),
 			
 			// 2MMM: Call subroutine
-			(0x2, _, _, _) => self.opcode_2mmm(op & 0xFFF),
+			(0x2, _, _, _) => |emu, op| emu.opcode_2mmm(op & 0xFFF),
 			
 			// 3XNN: Skip if V[x] == NN
-			(0x3, _, _, _) => self.opcode_3xnn(digit2, op & 0xFF),
+			(0x3, _, _, _) => |emu, op| emu.opcode_3xnn(op_x(op), op & 0xFF),
 			
 			// 4XNN: Skip if V[x] != NN
-			(0x4, _, _, _) => self.opcode_4xnn(digit2, op & 0xFF),
+			(0x4, _, _, _) => |emu, op| emu.opcode_4xnn(op_x(op), op & 0xFF),
 			
 			// 5XY0: Skip if V[x] == V[y]
-			(0x5, _, _, 0) => self.opcode_5xy0(digit2, digit3),
+			(0x5, _, _, 0) => |emu, op| emu.opcode_5xy0(op_x(op), op_y(op)),
 			
 			// 6XNN: V[x] = NN
-			(0x6, _, _, _) => self.opcode_6xnn(digit2, op & 0xFF),
+			(0x6, _, _, _) => |emu, op| emu.opcode_6xnn(op_x(op), op & 0xFF),
 				
 			// 7XNN: V[x] = V[x] + NN
-			(0x7, _, _, _) => self.opcode_7xnn(digit2, op & 0xFF),
+			(0x7, _, _, _) => |emu, op| emu.opcode_7xnn(op_x(op), op & 0xFF),
 			
 			// 8XY0: V[x] = V[y]
-			(0x8, _, _, 0) => self.opcode_8xy0(digit2, digit3),
+			(0x8, _, _, 0) => |emu, op| emu.opcode_8xy0(op_x(op), op_y(op)),
 			
 			// 8YX1; V[x] = V[x] OR V[y]
-			(0x8, _, _, 1) => self.opcode_8xy1(digit2, digit3),
+			(0x8, _, _, 1) => |emu, op| emu.opcode_8xy1(op_x(op), op_y(op)),
 			
 			// 8XY2: V[x] = V[x] AND V[y]
-			(0x8, _, _, 2) => self.opcode_8xy2(digit2, digit3),
+			(0x8, _, _, 2) => |emu, op| emu.opcode_8xy2(op_x(op), op_y(op)),
 
 			// 8XY3: V[x] = V[x] XOR V[y]
-			(0x8, _, _, 3) => self.opcode_8xy3(digit2, digit3),
+			(0x8, _, _, 3) => |emu, op| emu.opcode_8xy3(op_x(op), op_y(op)),
 			
 			// 8XY4: V[x] = V[x] + V[y], V[F] = 1 if overflow
-			(0x8, _, _, 4) => self.opcode_8xy4(digit2, digit3),
+			(0x8, _, _, 4) => |emu, op| emu.opcode_8xy4(op_x(op), op_y(op)),
 			
 			// 8XY5: V[x] = V[x] - V[y], V[0xF] = 1 if overflow
-			(0x8, _, _, 5) => self.opcode_8xy5(digit2, digit3),
+			(0x8, _, _, 5) => |emu, op| emu.opcode_8xy5(op_x(op), op_y(op)),
 			
 			// 8XY6: V[x] = V[x] >> 1
-			(0x8, _, _, 6) => self.opcode_8xy6(digit2, digit3),
+			(0x8, _, _, 6) => |emu, op| emu.opcode_8xy6(op_x(op), op_y(op)),
 			
 			// 8XY7: V[x] = V[y] - V[x]
-			(0x8, _, _, 7) => self.opcode_8xy7(digit2, digit3),
+			(0x8, _, _, 7) => |emu, op| emu.opcode_8xy7(op_x(op), op_y(op)),
 			
 			// 8XYE: V[x] = V[x] << 1
-			(0x8, _, _, 0xE) => self.opcode_8xye(digit2, digit3),
+			(0x8, _, _, 0xE) => |emu, op| emu.opcode_8xye(op_x(op), op_y(op)),
 			
 			// 9XY0: Skip if V[x] != V[y]
-			(0x9, _, _, 0) => self.opcode_9xy0(digit2, digit3),
+			(0x9, _, _, 0) => |emu, op| emu.opcode_9xy0(op_x(op), op_y(op)),
 			
 			// AMMM: I = MMM
-			(0xA, _, _, _) => self.opcode_ammm(op & 0xFFF),
+			(0xA, _, _, _) => |emu, op| emu.opcode_ammm(op & 0xFFF),
 			
+			// CHIP-8X replaces the jump with its color opcodes
+			// BXY0: Set the foreground color of zones to V[x+1]
+			// BXYN: Set the foreground color of N rows to V[x+1]
+			(0xB, _, _, _) if self.is_chip8x() => |emu, op| emu.opcode_bxyn(op_x(op), op_y(op), op_n(op)),
+
 			// BMMM: Jump to MMM + V[0]
-			(0xB, _, _, _) => self.opcode_bmmm(op & 0xFFF, digit2),
+			(0xB, _, _, _) => |emu, op| emu.opcode_bmmm(op & 0xFFF, op_x(op)),
 			
 			// CXNN: Get random byte, then AND with NN
-			(0xC, _, _, _) => self.opcode_cxnn(digit2, op & 0xFF),
+			(0xC, _, _, _) => |emu, op| emu.opcode_cxnn(op_x(op), op & 0xFF),
 			
 			// DXYN: Draw sprite of N rows at coordinates V[x], V[y]
-			(0xD, _, _, _) => self.opcode_dxyn(digit2, digit3, digit4),
+			(0xD, _, _, _) => |emu, op| emu.opcode_dxyn(op_x(op), op_y(op), op_n(op)),
 			
 			// EX9E - Skip if key VX is pressed
-			(0xE, _, 0x9, 0xE) => self.opcode_ex9e(digit2),
+			(0xE, _, 0x9, 0xE) => |emu, op| emu.opcode_ex9e(op_x(op)),
 
 			// EXA1 - Skip if key VX is not pressed
-			(0xE, _, 0xA, 0x1) => self.opcode_exa1(digit2),
+			(0xE, _, 0xA, 0x1) => |emu, op| emu.opcode_exa1(op_x(op)),
 			
 			// FX07 - VX = Time: Get current timer value
-			(0xF, _, 0x0, 0x7) => self.opcode_fx07(digit2),
+			(0xF, _, 0x0, 0x7) => |emu, op| emu.opcode_fx07(op_x(op)),
 			
 			// FX0A - Wait for key press
-			(0xF, _, 0x0, 0xA) => self.opcode_fx0a(digit2),
+			(0xF, _, 0x0, 0xA) => |emu, op| emu.opcode_fx0a(op_x(op)),
 			
 			// FX15: Initialize delay timer
-			(0xF, _, 0x1, 0x5) => self.opcode_fx15(digit2),
+			(0xF, _, 0x1, 0x5) => |emu, op| emu.opcode_fx15(op_x(op)),
 			
 			// FX18: Initialize sound timer
-			(0xF, _, 0x1, 0x8) => self.opcode_fx18(digit2),
+			(0xF, _, 0x1, 0x8) => |emu, op| emu.opcode_fx18(op_x(op)),
 			
 			// FX1E: Add V[x] to the memory pointer I
-			(0xF, _, 0x1, 0xE) => self.opcode_fx1e(digit2),
+			(0xF, _, 0x1, 0xE) => |emu, op| emu.opcode_fx1e(op_x(op)),
 			
 			// FX29: Set I to show digit V[x]
-			(0xF, _, 0x2, 0x9) => self.opcode_fx29(digit2),
+			(0xF, _, 0x2, 0x9) => |emu, op| emu.opcode_fx29(op_x(op)),
 			
 			// FX33: Store 3 digits of V[x] at M[I]
-			(0xF, _, 3, 3) => self.opcode_fx33(digit2),
+			(0xF, _, 3, 3) => |emu, op| emu.opcode_fx33(op_x(op)),
 			
 			// FX55: Store V[0] to V[x] at M[I]
-			(0xF, _, 5, 5) => self.opcode_fx55(digit2),
+			(0xF, _, 5, 5) => |emu, op| emu.opcode_fx55(op_x(op)),
 			
 			// FX65: Load V[0] to V[x] from M[I]
-			(0xF, _, 6, 5) => self.opcode_fx65(digit2),
+			(0xF, _, 6, 5) => |emu, op| emu.opcode_fx65(op_x(op)),
 			
 			// Opcodes introduced for the SCHIP variant
 			// 00FD: Exit interpreter
-			(0x0, 0x0, 0xF, 0xD) => self.opcode_00fd(),
+			(0x0, 0x0, 0xF, 0xD) => |emu, _| emu.opcode_00fd(),
 			
 			// 00FE: Disable high-resolution mode
-			(0x0, 0x0, 0xF, 0xE) => self.opcode_00fe(),
+			(0x0, 0x0, 0xF, 0xE) => |emu, _| emu.opcode_00fe(),
 		
 			// 00FF: Enable high-resolution mode
-			(0x0, 0x0, 0xF, 0xF) => self.opcode_00ff(),
+			(0x0, 0x0, 0xF, 0xF) => |emu, _| emu.opcode_00ff(),
 		
 			// FX75: Store V[0]-V[X] in RPL flags
-			(0xF, _, 0x7, 0x5) => self.opcode_fx75(digit2),
+			(0xF, _, 0x7, 0x5) => |emu, op| emu.opcode_fx75(op_x(op)),
 			
 			// FX85: Read V[0]-V[X] from RPL flags
 			// SCHIP: V <= 7, XOChip: V <= 15
-			(0xF, _, 0x8, 0x5) => self.opcode_fx85(digit2),
+			(0xF, _, 0x8, 0x5) => |emu, op| emu.opcode_fx85(op_x(op)),
 			
 			// 00CN: Scroll display N pixels down (N/2 in low resolution mode)
-			(0x0, 0x0, 0xC, _) => self.opcode_00cn(digit4),
+			(0x0, 0x0, 0xC, _) if self.has_scroll() => |emu, op| emu.opcode_00cn(op_n(op)),
 			
 			// 00FB: Scroll display right by 4 pixels (2 in low resolution mode)
-			(0x0, 0x0, 0xF, 0xB) => self.opcode_00fb(),
+			(0x0, 0x0, 0xF, 0xB) if self.has_scroll() => |emu, _| emu.opcode_00fb(),
 			
 			// 00FC: Scroll display left by 4 pixels (2 in low resolution mode)
-			(0x0, 0x0, 0xF, 0xC) => self.opcode_00fc(),
+			(0x0, 0x0, 0xF, 0xC) if self.has_scroll() => |emu, _| emu.opcode_00fc(),
 			
 			// FX30: Set I to 10-byte font for digit V[x] 
-			(0xF, _, 0x3, 0x0) => self.opcode_fx30(digit2),
+			(0xF, _, 0x3, 0x0) if self.config.variant != Variant::Chip48 => |emu, op| emu.opcode_fx30(op_x(op)),
 			
 			// Opcodes for the XOChip
 			// 00DN: Scroll display up by N pixels (N/2 in low resolution mode)
-			(0x0, 0x0, 0xD, _) => self.opcode_00dn(digit4),
+			(0x0, 0x0, 0xD, _) => |emu, op| emu.opcode_00dn(op_n(op)),
 			
 			// 5YX2: Save V[x] to V[y] in memory starting at I
-			(0x5, _, _, 0x2) => self.opcode_5xy2(digit2, digit3),
+			(0x5, _, _, 0x2) => |emu, op| emu.opcode_5xy2(op_x(op), op_y(op)),
 			
 			// 5YX3: Load V[x] to V[y] from memory starting at I
-			(0x5, _, _, 0x3) => self.opcode_5xy3(digit2, digit3),
+			(0x5, _, _, 0x3) => |emu, op| emu.opcode_5xy3(op_x(op), op_y(op)),
 			
 			// F000: Save the next 16 bits to I
 			// NOTE: This command reads 2 opcodes, so we must increment the PC again
-			(0xF, 0x0, 0x0, 0x0) => self.opcode_f000(),
+			(0xF, 0x0, 0x0, 0x0) => |emu, _| emu.opcode_f000(),
 			
 			// FN01: Select drawing plane(s)
-			(0xF, _, 0x0, 0x1) => self.opcode_fn01(digit2),
+			(0xF, _, 0x0, 0x1) => |emu, op| emu.opcode_fn01(op_x(op)),
 			
 			// F002: Store 16 bytes in audio pattern buffer
-			(0xF, 0x0, 0x0, 0x2) => self.opcode_f002(),
+			(0xF, 0x0, 0x0, 0x2) => |emu, _| emu.opcode_f002(),
 			// FX3A: Set the pitch register to V[x]
-			(0xF, _, 0x3, 0xA) => self.opcode_fx3a(digit2),
+			(0xF, _, 0x3, 0xA) => |emu, op| emu.opcode_fx3a(op_x(op)),
 			
-			(_, _, _, _) => unimplemented!("Unimplemented opcode: {}", op),
+			// Opcodes for the CHIP-8X
+			// 02A0: Cycle the background color
+			(0x0, 0x2, 0xA, 0x0) if self.is_chip8x() => |emu, _| emu.opcode_02a0(),
+
+			// 5XY1: Add each nibble of V[y] to the nibble of V[x]
+			(0x5, _, _, 0x1) if self.is_chip8x() => |emu, op| emu.opcode_5xy1(op_x(op), op_y(op)),
+
+			// EXF2: Skip if key V[x] of the second keypad is pressed
+			(0xE, _, 0xF, 0x2) if self.is_chip8x() => |emu, op| emu.opcode_exf2(op_x(op)),
+
+			// EXF5: Skip if key V[x] of the second keypad is not pressed
+			(0xE, _, 0xF, 0x5) if self.is_chip8x() => |emu, op| emu.opcode_exf5(op_x(op)),
+
+			// FXF8: Output V[x] to the I/O port
+			(0xF, _, 0xF, 0x8) if self.is_chip8x() => |emu, op| emu.opcode_fxf8(op_x(op)),
+
+			// FXFB: Read V[x] from the I/O port
+			(0xF, _, 0xF, 0xB) if self.is_chip8x() => |emu, op| emu.opcode_fxfb(op_x(op)),
+
+			// Opcode for the hi-res CHIP-8
+			// 0230: Clear screen
+			(0x0, 0x2, 0x3, 0x0) if self.config.variant == Variant::Chip8HiRes => |emu, _| emu.opcode_00e0(),
+
+			// Opcodes for the MegaChip
+			// 0010: Disable MEGA-CHIP mode
+			(0x0, 0x0, 0x1, 0x0) if self.is_megachip() => |emu, _| emu.opcode_0010(),
+
+			// 0011: Enable MEGA-CHIP mode
+			(0x0, 0x0, 0x1, 0x1) if self.is_megachip() => |emu, _| emu.opcode_0011(),
+
+			// 00BN: Scroll display up by N pixels
+			(0x0, 0x0, 0xB, _) if self.is_megachip() => |emu, op| emu.opcode_00bn(op_n(op)),
+
+			// 01NN NNNN: Save the 24-bit address NNNNNN to I
+			// NOTE: This command reads 2 opcodes, so we must increment the PC again
+			(0x0, 0x1, _, _) if self.is_megachip() => |emu, op| emu.opcode_01nn(op & 0xFF),
+
+			// 02NN: Load NN colors of the palette from I
+			(0x0, 0x2, _, _) if self.is_megachip() => |emu, op| emu.opcode_02nn(op & 0xFF),
+
+			// 03NN: Set the sprite width to NN
+			(0x0, 0x3, _, _) if self.is_megachip() => |emu, op| emu.opcode_03nn(op & 0xFF),
+
+			// 04NN: Set the sprite height to NN
+			(0x0, 0x4, _, _) if self.is_megachip() => |emu, op| emu.opcode_04nn(op & 0xFF),
+
+			// 05NN: Set the screen alpha to NN
+			(0x0, 0x5, _, _) if self.is_megachip() => |emu, _| emu.opcode_05nn(),
+
+			// 060N: Play the digitized sound at I, once if N is 1 or looping if N is 0
+			(0x0, 0x6, 0x0, _) if self.is_megachip() => |emu, op| emu.opcode_060n(op_n(op)),
+
+			// 0700: Stop the digitized sound
+			(0x0, 0x7, 0x0, 0x0) if self.is_megachip() => |emu, _| emu.opcode_0700(),
+
+			// 080N: Select the blend mode
+			(0x0, 0x8, 0x0, _) if self.is_megachip() => |emu, op| emu.opcode_080n(op_n(op)),
+
+			// 09NN: Set the collision color to NN
+			(0x0, 0x9, _, _) if self.is_megachip() => |emu, op| emu.opcode_09nn(op & 0xFF),
+
+			// 0NNN: Call the machine code subroutine at NNN
+			(0x0, _, _, _) if self.config.variant.is_vip() => |emu, op| emu.opcode_0nnn(op & 0xFFF),
+
+			// Anything else does not exist on the variant
+			(_, _, _, _) => |emu, op| emu.opcode_invalid(op),
 		}
 	}
 
 	// Clears selected plane
 	fn clear_screen(&mut self, plane_index: usize) {
-		for pixel in self.screen[plane_index].iter_mut() {
-			*pixel = false;
+		self.mark_plane_dirty(plane_index);
+		self.screen[plane_index].fill(0);
+	}
+
+	// Add a rectangle to the part of the screen that changed
+	fn mark_dirty(&mut self, rect: DirtyRect) {
+		self.dirty = Some(match self.dirty {
+			Some(dirty) => dirty.union(&rect),
+			None => rect,
+		});
+	}
+
+	// Mark the whole screen as changed
+	fn mark_screen_dirty(&mut self) {
+		let (width, height) = self.get_render_size(1);
+		self.mark_dirty(DirtyRect { x: 0, y: 0, width, height });
+	}
+
+	// Mark the pixels of a row between the first and the last set bit of
+	// changed as changed
+	fn mark_row_dirty(&mut self, y: usize, changed: u128) {
+		if changed != 0 {
+			let x = changed.leading_zeros() as usize;
+			let width = 128 - changed.trailing_zeros() as usize - x;
+			self.mark_dirty(DirtyRect { x, y, width, height: 1 });
 		}
 	}
+
+	// Mark the lit pixels of a plane as changed. Done before and after the
+	// plane moves, which covers every pixel that changed
+	fn mark_plane_dirty(&mut self, plane_index: usize) {
+		for y in 0..self.screen_height {
+			self.mark_row_dirty(y, self.screen[plane_index][y]);
+		}
+	}
+
+	// The bits of a screen row that hold pixels: the screen_width highest ones
+	fn row_mask(&self) -> u128 {
+		!0 << (128 - self.screen_width)
+	}
+
+	// Move the pixels of a row right by x, wrapping the ones that go past the
+	// right edge of the screen around to the left edge
+	fn wrap_row(&self, row: u128, x: usize) -> u128 {
+		let wrapped = row.checked_shl((self.screen_width - x) as u32).unwrap_or(0);
+		((row >> x) | wrapped) & self.row_mask()
+	}
+
+	// XOR the pixels of a row into the screen, and return true if any pixel
+	// that was on got turned off
+	fn xor_row(&mut self, plane_index: usize, y: usize, pixels: u128) -> bool {
+		let row = &mut self.screen[plane_index][y];
+		let collision = *row & pixels != 0;
+		*row ^= pixels;
+		self.mark_row_dirty(y, pixels);
+		collision
+	}
 	
-	// Draws sprites on the screen by writing bits on the screen buffers
-	fn draw_sprite(&mut self, x_base: u16, y_base: u16, n: u8, base_address: u16, plane_index: usize) {
-		let screen = &mut self.screen[plane_index];
+	// Draws sprites on the screen by XORing their rows into the screen rows.
+	// Every row is a u128 with the leftmost pixel in the highest bit
+	fn draw_sprite(&mut self, x_base: u16, y_base: u16, n: u8, base_address: u32, plane_index: usize) {
 		let mut width = if n == 0 {
 			2
 		} else {
 			1
 		};
-		let mut mask = if n == 0 {
-			0b1000_0000_0000_0000
-		} else {
-			0b1000_0000
-		};
 		
 		let num_rows = if n == 0 {16} else {n};
 		let mut total_flipped_rows = 0;
@@ -472,114 +1519,55 @@ impl Emulator {
 		let mut clipped_rows = 0;
 		
 		// Lowres mode on SChip only draws 8x16 (8 width, 16 rows)
-		if n == 0 && self.config.variant == Variant::SChip  &&
+		if n == 0 && self.config.quirk_lowressprite &&
 			self.high_res_mode == false {
 				width = 1;
-				mask = 0b1000_0000;
 			}
+
+		// On low resolution mode in these variants each pixel draw counts as
+		// a 2x2 pixel on the final screen
+		let double_pixels = self.high_res_mode == false && matches!(self.config.variant,
+			Variant::SChip10 | Variant::SChip | Variant::XOChip | Variant::MegaChip);
+		let (x_base, y_base) = if double_pixels {
+			// Get the x_base and y_base again since we need to wrap around the
+			// low_res dimensions
+			(2 * (x_base as usize % (self.screen_width / 2)), 2 * (y_base as usize % (self.screen_height / 2)))
+		} else {
+			(x_base as usize, y_base as usize)
+		};
+		let row_mask = self.row_mask();
 		
-		for row in 0..num_rows {
-			let address = (base_address + width*row as u16) as usize;
+		for row in 0..num_rows as usize {
+			let address = base_address as usize + width as usize * row;
 			let pixels = if width == 2 {
-				((self.ram[address] as u16) << 8) + self.ram[address + 1] as u16
+				((self.read_ram(address) as u16) << 8) + self.read_ram(address + 1) as u16
 			} else {
-				self.ram[address] as u16
+				self.read_ram(address) as u16
+			};
+			// The sprite row, with its leftmost pixel in the highest bit
+			let (sprite_row, y) = if double_pixels {
+				let doubled = double_bits(pixels);
+				((doubled as u128) << (128 - 16 * width as usize), y_base + 2 * row)
+			} else {
+				((pixels as u128) << (128 - 8 * width as usize), y_base + row)
 			};
-			let mut is_current_row_flipped = false;
-			for column in 0..(width * 8) {
-				if (pixels & (mask >> column)) != 0 {
-					if self.high_res_mode == true {
-						if self.config.quirk_clipping {
-							let x = (x_base + column as u16) as usize;
-							let y = (y_base + row as u16) as usize;
-							let index = x + self.screen_width * y;
-							if x < self.screen_width && y < self.screen_height {
-								is_current_row_flipped |= screen[index];
-								screen[index] ^= true;
-							}
-							else if y >= self.screen_height && self.config.quirk_clipcollision {
-								clipped_rows += 1;
-							}
-						}
-						else {
-							let x = (x_base + column as u16) as usize % self.screen_width;
-							let y = (y_base + row as u16) as usize % self.screen_height;
-							let index = x + self.screen_width * y;
-							is_current_row_flipped |= screen[index];
-							screen[index] ^= true;
-						}
-					}
-				
-					else if self.high_res_mode == false {
-						match self.config.variant {
-							Variant::Chip8 => {
-								if self.config.quirk_clipping {
-									let x = (x_base + column) as usize;
-									let y = (y_base + row as u16) as usize;
-
-									let index = x + self.screen_width * y;
-
-									if x < self.screen_width && y < self.screen_height {
-										is_current_row_flipped |= screen[index];
-										screen[index] ^= true;	
-									}
-								}
-								else {
-									let x = (x_base + column as u16) as usize % self.screen_width;
-									let y = (y_base + row as u16) as usize % self.screen_height;
-									let index = x + self.screen_width * y;
-									is_current_row_flipped |= screen[index];
-									screen[index] ^= true;
-								}
-							}
-							Variant::SChip | Variant::XOChip => {
-								// On low resolution mode in these 2 variants
-								// Each pixel draw counts as a 2x2 pixel on the
-								// final screen
-								
-								// Get the x_base and y_base again since we
-								// need to wrap around the low_res dimensions
-								let x_base = x_base % ((self.screen_width / 2) as u16);
-								let y_base = y_base % ((self.screen_height / 2) as u16);
-								if self.config.quirk_clipping {
-									let x = 2 * (x_base + column as u16) as usize;
-									let y = 2 * (y_base + row as u16) as usize;
-									let index = x + self.screen_width * y;
-									if x < self.screen_width && y < self.screen_height {
-										is_current_row_flipped |= screen[index];
-										screen[index] ^= true;
-
-										is_current_row_flipped |= screen[index+1];
-										screen[index+1] ^= true;
-
-										is_current_row_flipped |= screen[index+self.screen_width];
-										screen[index+self.screen_width] ^= true;
 
-		
-										is_current_row_flipped |= screen[index+self.screen_width+1];
-										screen[index+self.screen_width+1] ^= true;
-									}		
-								}
-								else {
-									let x = 2 * (x_base + column as u16) as usize % self.screen_width;
-									let y = 2 * (y_base + row as u16) as usize % self.screen_height;
-									let index = x + self.screen_width * y;
-									is_current_row_flipped |= screen[index];
-									screen[index] ^= true;
-
-									is_current_row_flipped |= screen[index+1];
-									screen[index+1] ^= true;
-
-									is_current_row_flipped |= screen[index+self.screen_width];
-									screen[index+self.screen_width] ^= true;
-
-									is_current_row_flipped |= screen[index+self.screen_width+1];
-									screen[index+self.screen_width+1] ^= true;
-								}
-							}
-						}
+			let (screen_row, y) = if self.config.quirk_clipping {
+				if y >= self.screen_height {
+					// Only counted in high resolution mode
+					if self.high_res_mode && self.config.quirk_clipcollision {
+						clipped_rows += pixels.count_ones() as u8;
 					}
+					continue;
 				}
+				((sprite_row >> x_base) & row_mask, y)
+			} else {
+				(self.wrap_row(sprite_row, x_base), y % self.screen_height)
+			};
+
+			let mut is_current_row_flipped = self.xor_row(plane_index, y, screen_row);
+			if double_pixels {
+				is_current_row_flipped |= self.xor_row(plane_index, y + 1, screen_row);
 			}
 			if is_current_row_flipped {
 			total_flipped_rows += 1;
@@ -588,7 +1576,7 @@ impl Emulator {
 		// On SUPER-CHIP in high resolution mode, V[0xF] is set to the number
 		// or rows that have been flipped + the number of clipped rows at the
 		// bottom edge
-		if self.high_res_mode == true && self.config.variant == Variant::SChip {
+		if self.high_res_mode == true && self.config.variant.is_schip() {
 			self.v_register[0xF] = total_flipped_rows + clipped_rows;
 		}
 		// Otherwise, V[0xF] is set 1 if at least 1 pixel has been flipped
@@ -602,84 +1590,138 @@ impl Emulator {
 		}
 	}
 
+	// Draws a MEGA-CHIP sprite of sprite_width x sprite_height palette
+	// indices. Index 0 is transparent. Sprites read from below the load
+	// address are font characters, and they are drawn as regular 8xN sprites
+	// with color 255. V[0xF] is set to 1 if a pixel of the collision color
+	// was drawn over
+	fn draw_mega_sprite(&mut self, x_base: usize, y_base: usize, n: u8) {
+		let base_address = self.i_register as usize;
+		let is_font = base_address < self.config.load_address as usize;
+		let (width, height) = if is_font {
+			(8, if n == 0 {16} else {n as usize})
+		} else {
+			(self.sprite_width, self.sprite_height)
+		};
+
+		let mut collision = false;
+		for row in 0..height {
+			let y = y_base + row;
+			if y >= MEGA_SCREEN_HEIGHT {
+				break;
+			}
+			for column in 0..width {
+				let x = x_base + column;
+				if x >= MEGA_SCREEN_WIDTH {
+					break;
+				}
+				let color_index = if is_font {
+					let pixels = self.read_ram(base_address + row);
+					if pixels & (0b1000_0000 >> column) != 0 {255} else {0}
+				} else {
+					self.read_ram(base_address + row * width + column)
+				};
+				if color_index == 0 {
+					continue;
+				}
+				let index = x + MEGA_SCREEN_WIDTH * y;
+				collision |= self.mega_indices[index] == self.collision_color;
+				self.mega_indices[index] = color_index;
+				self.mega_frame[index] = blend(self.mega_palette[color_index as usize], self.mega_frame[index], self.blend_mode);
+			}
+		}
+		self.v_register[0xF] = collision as u8;
+	}
+
+	// Clears the MEGA-CHIP frame that is being drawn
+	fn clear_mega_screen(&mut self) {
+		self.mega_indices.fill(0);
+		self.mega_frame.fill(0);
+	}
+
+	// Helper function to scroll the MEGA-CHIP screen by dx, dy pixels
+	fn scroll_mega(&mut self, dx: isize, dy: isize) {
+		let old_indices = self.mega_indices.clone();
+		let old_frame = self.mega_frame.clone();
+		self.clear_mega_screen();
+		for y in 0..MEGA_SCREEN_HEIGHT {
+			for x in 0..MEGA_SCREEN_WIDTH {
+				let new_x = x as isize + dx;
+				let new_y = y as isize + dy;
+				if new_x < 0 || new_y < 0 || new_x >= MEGA_SCREEN_WIDTH as isize || new_y >= MEGA_SCREEN_HEIGHT as isize {
+					continue;
+				}
+				let old_index = x + MEGA_SCREEN_WIDTH * y;
+				let new_index = new_x as usize + MEGA_SCREEN_WIDTH * new_y as usize;
+				self.mega_indices[new_index] = old_indices[old_index];
+				self.mega_frame[new_index] = old_frame[old_index];
+			}
+		}
+	}
+
 	// Helper function to scroll up a given plane
 	fn scroll_up(&mut self, n: u8, plane_index: usize) {
+		self.mark_plane_dirty(plane_index);
 		let screen = &mut self.screen[plane_index];
 
 		let scroll_value = if self.high_res_mode == false && !self.config.quirk_legacyscroll {
 			2*n as usize
 		} else {
 			n as usize
-		};
-		for x in 0..self.screen_width {
-			for y in 0..self.screen_height {
-				if screen[y * self.screen_width + x] == true
-					&& y >= scroll_value {
-						screen[(y-scroll_value) * self.screen_width + x] = true;
-					}
-				screen[y * self.screen_width + x] = false;
-			}
-		}
+		}.min(self.screen_height);
+		screen.rotate_left(scroll_value);
+		let height = screen.len();
+		screen[height - scroll_value..].fill(0);
+		self.mark_plane_dirty(plane_index);
 	}
 	
 	// Helper function to scroll down a given plane
 	fn scroll_down(&mut self, n: u8, plane_index: usize) {
+		self.mark_plane_dirty(plane_index);
 		let screen = &mut self.screen[plane_index];
 		let scroll_value = if self.high_res_mode == false && !self.config.quirk_legacyscroll {
 			2*n as usize
 		} else {
 			n as usize
-		};
-		for x in 0..self.screen_width {
-			for y in (0..self.screen_height).rev() {
-				if screen[y * self.screen_width + x] == true
-					&& y < self.screen_height - scroll_value {
-						screen[(y+scroll_value) * self.screen_width + x] = true;
-					}
-				screen[y * self.screen_width + x] = false;
-			}
-		}
+		}.min(self.screen_height);
+		screen.rotate_right(scroll_value);
+		screen[..scroll_value].fill(0);
+		self.mark_plane_dirty(plane_index);
 	}
 	// Helper function to scroll right a given plane
 	fn scroll_right(&mut self, plane_index: usize) {
-		let screen = &mut self.screen[plane_index];
+		self.mark_plane_dirty(plane_index);
+		let row_mask = self.row_mask();
 		let scroll_value = if self.high_res_mode == false && !self.config.quirk_legacyscroll {
-			8 as usize
+			8
 		} else {
-			4 as usize
+			4
 		};
-		for y in 0..self.screen_height {
-			for x in (0..self.screen_width).rev() {
-				if screen[y * self.screen_width + x] == true
-					&& x <	self.screen_width - scroll_value {
-						screen[y * self.screen_width+x+scroll_value] = true;
-					}
-				screen[y * self.screen_width + x] = false;	
-			}
+		for row in self.screen[plane_index].iter_mut() {
+			*row = (*row >> scroll_value) & row_mask;
 		}
+		self.mark_plane_dirty(plane_index);
 	}
 	
 	// Helper function to scroll left a given plane
 	fn scroll_left(&mut self, plane_index: usize) {
-		let screen = &mut self.screen[plane_index];
+		self.mark_plane_dirty(plane_index);
 		let scroll_value = if self.high_res_mode == false && !self.config.quirk_legacyscroll {
-			8 as usize
+			8
 		} else {
-			4 as usize
+			4
 		};
-		for y in 0..self.screen_height {
-			for x in 0..self.screen_width {
-				if screen[y * self.screen_width + x] == true
-					&& x >= scroll_value {
-						screen[y * self.screen_width+x-scroll_value] = true;
-					}
-				screen[y * self.screen_width + x] = false;
-			}
-		}				
+		for row in self.screen[plane_index].iter_mut() {
+			*row <<= scroll_value;
+		}
+		self.mark_plane_dirty(plane_index);
 	}
 
 	// Convert the pitch register to the actual frequency we will use for audio
 	pub fn get_sound_frequency(&self) -> i32 {
+		if self.config.variant == Variant::Chip8X {
+			return 27535 / (self.port_output as i32 + 1);
+		}
 		return (4000.0 * 2_i32.pow(((self.pitch - 64)/48) as u32) as f64) as i32;
 	}
 	
@@ -688,8 +1730,64 @@ impl Emulator {
 		return;
 	}
 
+	// 0NNN: Call the machine code subroutine at NNN. The subroutine sees the
+	// registers where the VIP interpreter keeps them: V[0]-V[F] at 0xEF0, I
+	// in R(A), the PC in R(5), the stack in R(2) and the display page in R(B)
+	// (the addresses are relative to the end of a 4K RAM). It returns with
+	// SEP R4 (D4)
+	fn opcode_0nnn(&mut self, nnn: u16) {
+		let work_area = self.vip_work_area();
+		let v_address = work_area + VIP_V_REGISTERS_OFFSET;
+		self.ram[v_address..v_address + NUM_REGISTERS].copy_from_slice(&self.v_register);
+
+		// The machine code gets the stack below the entries of the CHIP-8
+		// stack that are in RAM
+		self.cpu.r[2] = if self.config.vip_memory_map {
+			self.vip_stack_address(self.stack_pointer) as u16
+		} else {
+			(work_area + VIP_STACK_OFFSET) as u16
+		};
+		self.cpu.r[3] = nnn;
+		self.cpu.r[5] = self.pc;
+		self.cpu.r[0xA] = self.i_register as u16;
+		self.cpu.r[0xB] = (work_area + VIP_DISPLAY_OFFSET) as u16;
+		self.cpu.p = 3;
+		self.cpu.x = 2;
+		for _ in 0..MAX_MACHINE_CODE_STEPS {
+			self.instruction_cycles += self.cpu.step(&mut self.ram);
+			if self.cpu.p == 4 {
+				break;
+			}
+		}
+
+		self.v_register.copy_from_slice(&self.ram[v_address..v_address + NUM_REGISTERS]);
+		self.i_register = self.cpu.r[0xA] as u32;
+		// The machine code may have written anywhere
+		self.clear_code_cache();
+	}
+
+	fn vip_work_area(&self) -> usize {
+		vip_work_area(self.ram_size)
+	}
+
+	// An instruction the variant does not decode. With config.strict it is
+	// reported and halts the program, otherwise it is skipped
+	fn opcode_invalid(&mut self, op: u16) {
+		if self.config.strict {
+			self.report_opcode(op);
+			self.halted = true;
+		}
+	}
+
 	// 00E0: Clear screen
 	fn opcode_00e0(&mut self) {
+		// In MEGA-CHIP mode this also shows the finished frame
+		if self.mega_mode {
+			self.mega_display.copy_from_slice(&self.mega_frame);
+			self.clear_mega_screen();
+			self.mark_screen_dirty();
+			return;
+		}
 		for i in 0..self.num_planes {
 			let bit = (self.selected_planes >> i) & 0b0001;
 			if bit == 1 {
@@ -722,10 +1820,11 @@ impl Emulator {
 		let v_number = x as usize;
 		let number_to_compare = nn as u8;
 		if self.v_register[v_number] == number_to_compare {
-			self.pc += 2;
+			self.pc = self.pc.wrapping_add(2);
 			if self.next_opcode_double {
-				self.pc += 2;
+				self.pc = self.pc.wrapping_add(2);
 			}
+			self.skip_cycles();
 		}
 	}
 
@@ -734,10 +1833,11 @@ impl Emulator {
 		let v_index = x as usize;
 		let number_to_compare = nn as u8;
 		if self.v_register[v_index] != number_to_compare {
-			self.pc += 2;
+			self.pc = self.pc.wrapping_add(2);
 			if self.next_opcode_double {
-				self.pc += 2;
+				self.pc = self.pc.wrapping_add(2);
 			}
+			self.skip_cycles();
 		}
 	}
 
@@ -746,10 +1846,11 @@ impl Emulator {
 		let v_index1 = x as usize;
 		let v_index2 = y as usize;
 		if self.v_register[v_index1] == self.v_register[v_index2] {
-			self.pc += 2;
+			self.pc = self.pc.wrapping_add(2);
 			if self.next_opcode_double {
-				self.pc += 2;
+				self.pc = self.pc.wrapping_add(2);
 			}
+			self.skip_cycles();
 		}
 	}
 
@@ -840,8 +1941,8 @@ impl Emulator {
 			self.v_register[index1] = self.v_register[index2];
 		}
 		let lsb = self.v_register[index1] & 1;
-		self.v_register[index1] >>= 1;
-		self.v_register[0xF] = lsb;
+		let result = self.v_register[index1] >> 1;
+		self.set_result_and_flag(index1, result, lsb);
 	}
 	
 	// 8XY7: V[x] = V[y] - V[x]
@@ -866,26 +1967,39 @@ impl Emulator {
 			self.v_register[index1] = self.v_register[index2];
 		}
 		let msb = (self.v_register[index1] >> 7) & 1;
-		self.v_register[index1] <<= 1;
-		self.v_register[0xF] = msb;
+		let result = self.v_register[index1] << 1;
+		self.set_result_and_flag(index1, result, msb);
 	}	
 
+	// Store the result of a shift and its flag. Normally the flag is stored
+	// last, with quirk_vforder the result is
+	fn set_result_and_flag(&mut self, index: usize, result: u8, flag: u8) {
+		if self.config.quirk_vforder {
+			self.v_register[0xF] = flag;
+			self.v_register[index] = result;
+		} else {
+			self.v_register[index] = result;
+			self.v_register[0xF] = flag;
+		}
+	}
+
 	// 9XY0: Skip if V[x] != V[y]
 	fn opcode_9xy0(&mut self, x: u8, y: u8) {
 		let v_index1 = x as usize;
 		let v_index2 = y as usize;
 		if self.v_register[v_index1] != self.v_register[v_index2] {
-			self.pc += 2;
+			self.pc = self.pc.wrapping_add(2);
 			if self.next_opcode_double {
-				self.pc += 2;
+				self.pc = self.pc.wrapping_add(2);
 			}
+			self.skip_cycles();
 		}
 	}
 	
 	// AMMM: I = MMM
 	fn opcode_ammm(&mut self, mmm: u16) {
 		let index = mmm;
-		self.i_register = index;
+		self.i_register = index as u32;
 	}
 	
 	// BMMM: Jump to MMM + V[0]
@@ -909,16 +2023,23 @@ impl Emulator {
 
 	// DXYN: Draw sprite of N rows at coordinates V[x], V[y]
 	fn opcode_dxyn(&mut self, x: u8, y: u8, n: u8) {
+		if self.mega_mode {
+			let x_base = self.v_register[x as usize] as usize;
+			let y_base = self.v_register[y as usize] as usize;
+			self.draw_mega_sprite(x_base, y_base, n);
+			return;
+		}
 		let x_base = (self.v_register[x as usize] %
 					  self.screen_width as u8) as u16;
 		let y_base = (self.v_register[y as usize] %
 					  self.screen_height as u8) as u16;
 		
 		match self.config.variant {
-			Variant::Chip8 | Variant::SChip => {
+			Variant::Chip8 | Variant::Chip8HiRes | Variant::Chip10 | Variant::Chip8X | Variant::Chip48 |
+			Variant::SChip10 | Variant::SChip | Variant::MegaChip => {
 				if self.config.quirk_displaywait && self.high_res_mode == false {
 					if self.key_frame == false {
-						self.pc -= 2;
+						self.pc = self.pc.wrapping_sub(2);
 						return;
 					}
 				}
@@ -930,13 +2051,13 @@ impl Emulator {
 			Variant::XOChip => {
 				if self.config.quirk_displaywait && self.high_res_mode == false {
 					if self.key_frame == false {
-						self.pc -= 2;
+						self.pc = self.pc.wrapping_sub(2);
 						return;
 					}
 				}
 				
 				let mut base_address = self.i_register;
-				let width = if n == 0 && self.high_res_mode == true {
+				let width = if n == 0 && (self.high_res_mode || !self.config.quirk_lowressprite) {
 					2
 				} else {
 					1
@@ -947,7 +2068,7 @@ impl Emulator {
 					let bit = (self.selected_planes >> i) & 0b0001;
 					if bit == 1 {
 						self.draw_sprite(x_base, y_base, n, base_address, i as usize);
-						base_address += width*num_rows as u16;
+						base_address += (width * num_rows as u16) as u32;
 					}
 				}
 			}
@@ -964,10 +2085,11 @@ impl Emulator {
 		let vx = self.v_register[index] & 0xF;
 		let key = self.keys[vx as usize];
 		if key {
-			self.pc += 2;
+			self.pc = self.pc.wrapping_add(2);
 			if self.next_opcode_double {
-				self.pc += 2;
+				self.pc = self.pc.wrapping_add(2);
 			}
+			self.skip_cycles();
 		}	
 	}
 
@@ -977,10 +2099,11 @@ impl Emulator {
 		let vx = self.v_register[index] & 0xF;
 		let key = self.keys[vx as usize];
 		if !key {
-			self.pc += 2;
+			self.pc = self.pc.wrapping_add(2);
 			if self.next_opcode_double {
-				self.pc += 2;
+				self.pc = self.pc.wrapping_add(2);
 			}
+			self.skip_cycles();
 		}
 	}
 
@@ -995,8 +2118,11 @@ impl Emulator {
 		let index = x as usize;
 		for i in 0..self.keys.len() {
 			// We register a release if a key was pressed on the previous frame
-			// and not pressed on the current
-			if self.keys[i] == false && self.previous_frame_keys[i] == true {
+			// and not pressed on the current. With quirk_keypress, it is the
+			// other way around
+			let pressed_before = self.previous_frame_keys[i] != self.config.quirk_keypress;
+			let pressed_now = self.keys[i] != self.config.quirk_keypress;
+			if pressed_now == false && pressed_before == true {
 				self.v_register[index] = i as u8;
 				released = true;
 				break;
@@ -1004,7 +2130,7 @@ impl Emulator {
 		}
 		// If no key was pressed, rewind the PC
 		if !released {
-			self.pc -= 2;
+			self.pc = self.pc.wrapping_sub(2);
 		}
 	}
 
@@ -1021,13 +2147,17 @@ impl Emulator {
 	// FX1E: Add V[x] to the memory pointer I
 	fn opcode_fx1e(&mut self, x: u8) {
 		let index = x as usize;
-		self.i_register = self.i_register.wrapping_add(self.v_register[index] as u16);
+		let sum = self.i_register + self.v_register[index] as u32;
+		if self.config.quirk_indexoverflow {
+			self.v_register[0xF] = (sum > 0xFFF) as u8;
+		}
+		self.i_register = sum & self.index_mask();
 	}
 	// FX29: Set I to show digit V[x]
 	fn opcode_fx29(&mut self, x: u8) {
 		let index = x as usize;
 		let character =(self.v_register[index] & 0xF as u8) as u16;
-		self.i_register = character * 5;
+		self.i_register = (self.config.font_address + character * 5) as u32;
 		
 	}
 	// FX33: Store 3 digits of V[x] at M[I]
@@ -1037,43 +2167,63 @@ impl Emulator {
 		let ones = value % 10;
 		let tens = (value / 10) % 10;
 		let hundreds = value / 100;
-		self.ram[self.i_register as usize] = hundreds;
-		self.ram[(self.i_register+1) as usize] = tens;
-		self.ram[(self.i_register+2) as usize] = ones;
+		self.write_ram(self.i_register as usize, hundreds);
+		self.write_ram(self.i_register as usize + 1, tens);
+		self.write_ram(self.i_register as usize + 2, ones);
 	}
 
 	// FX55: Store V[0] to V[x] at M[I]
 	fn opcode_fx55(&mut self, x: u8) {
 		let last_index = x as usize;
 		for i in 0..=last_index {
-			let ram_index = (self.i_register + i as u16) as usize;
-			self.ram[ram_index] = self.v_register[i];
-		}
-		if self.config.quirk_memory {
-			self.i_register = self.i_register + last_index as u16 + 1;
+			let ram_index = self.i_register as usize + i;
+			self.write_ram(ram_index, self.v_register[i]);
 		}
+		self.increment_index_after_memory(last_index);
 	}
 	// FX65: Load V[0] to V[x] from M[I]
 	fn opcode_fx65(&mut self, x: u8) {
 		let last_index = x as usize;
 		for i in 0..=last_index {
-			let ram_index = (self.i_register + i as u16) as usize;
-			self.v_register[i] = self.ram[ram_index];
+			let ram_index = self.i_register as usize + i;
+			self.v_register[i] = self.read_ram(ram_index);
 		}
+		self.increment_index_after_memory(last_index);
+	}
+
+	// With the memory quirk, FX55 and FX65 leave I pointing after the last
+	// register (or at it, on CHIP-48 and SUPER-CHIP 1.0)
+	fn increment_index_after_memory(&mut self, last_index: usize) {
 		if self.config.quirk_memory {
-			self.i_register = self.i_register + last_index as u16 + 1;
-		}								
+			let increment = if self.config.quirk_memoryincrementbyx {
+				last_index as u16
+			} else {
+				last_index as u16 + 1
+			};
+			self.i_register = (self.i_register + increment as u32) & self.index_mask();
+		}
+	}
+
+	// The bits of I that are kept when it wraps around
+	fn index_mask(&self) -> u32 {
+		if self.config.quirk_index12bit {
+			0xFFF
+		} else if self.config.variant == Variant::MegaChip {
+			0xFF_FFFF
+		} else {
+			0xFFFF
+		}
 	}
 
 	// Opcodes introduced for the SCHIP variant
-	// 00FD: Exit interpreter
+	// 00FD: Exit interpreter. The emulator halts until it is reset
 	fn opcode_00fd(&mut self) {
-		self.pc = 0x200;
+		self.halted = true;
 	}
 	// 00FE: Disable high-resolution mode
 	fn opcode_00fe(&mut self) {
 		self.high_res_mode = false;
-		if self.config.variant == Variant::XOChip {
+		if self.config.quirk_resclear {
 			for i in 0..self.num_planes {
 				self.clear_screen(i as usize);
 			}
@@ -1083,7 +2233,7 @@ impl Emulator {
 	// 00FF: Enable high-resolution mode
 	fn opcode_00ff(&mut self) {
 		self.high_res_mode = true;
-		if self.config.variant == Variant::XOChip {
+		if self.config.quirk_resclear {
 			for i in 0..self.num_planes {
 				self.clear_screen(i as usize);
 			}
@@ -1103,6 +2253,10 @@ impl Emulator {
 
 	// 00CN: Scroll display N pixels down (N/2 in low resolution mode)
 	fn opcode_00cn(&mut self, n: u8) {
+		if self.mega_mode {
+			self.scroll_mega(0, n as isize);
+			return;
+		}
 		for i in 0..self.num_planes {
 			let bit = (self.selected_planes >> i) & 0b0001;
 			if bit == 1 {
@@ -1112,6 +2266,10 @@ impl Emulator {
 	}
 	// 00FB: Scroll display right by 4 pixels (2 in low resolution mode)
 	fn opcode_00fb(&mut self) {
+		if self.mega_mode {
+			self.scroll_mega(4, 0);
+			return;
+		}
 		for i in 0..self.num_planes {
 			let bit = (self.selected_planes >> i) & 0b0001;
 			if bit == 1 {
@@ -1121,6 +2279,10 @@ impl Emulator {
 	}
 	// 00FC: Scroll display left by 4 pixels (2 in low resolution mode)
 	fn opcode_00fc(&mut self) {
+		if self.mega_mode {
+			self.scroll_mega(-4, 0);
+			return;
+		}
 		for i in 0..self.num_planes {
 			let bit = (self.selected_planes >> i) & 0b0001;
 			if bit == 1 {
@@ -1132,7 +2294,7 @@ impl Emulator {
 	fn opcode_fx30(&mut self, x: u8) {
 		let index = x as usize;
 		let character = (self.v_register[index] & 0xF) as u16;
-		self.i_register = FONTSET_SIZE as u16 + character * 10;
+		self.i_register = (self.config.big_font_address + character * 10) as u32;
 	}
 
 	
@@ -1153,14 +2315,14 @@ impl Emulator {
 		let last_index = y as usize;
 		if first_index <= last_index {
 			for i in 0..=(last_index - first_index) {
-				let ram_index = (self.i_register + i as u16) as usize;
-				self.ram[ram_index] = self.v_register[first_index + i];
+				let ram_index = self.i_register as usize + i;
+				self.write_ram(ram_index, self.v_register[first_index + i]);
 			}
 		}
 		else {
 			for i in 0..=(first_index - last_index) {
-				let ram_index = (self.i_register + i as u16) as usize;
-				self.ram[ram_index] = self.v_register[first_index - i];
+				let ram_index = self.i_register as usize + i;
+				self.write_ram(ram_index, self.v_register[first_index - i]);
 			}
 		}
 		
@@ -1171,14 +2333,14 @@ impl Emulator {
 		let last_index = y as usize;
 		if first_index <= last_index {
 			for i in 0..=(last_index - first_index) {
-				let ram_index = (self.i_register + i as u16) as usize;
-				self.v_register[first_index + i] = self.ram[ram_index];
+				let ram_index = self.i_register as usize + i;
+				self.v_register[first_index + i] = self.read_ram(ram_index);
 			}	
 		}
 		else {
 			for i in 0..(first_index - last_index) {
-				let ram_index = (self.i_register + i as u16) as usize;
-				self.v_register[first_index - i] = self.ram[ram_index];
+				let ram_index = self.i_register as usize + i;
+				self.v_register[first_index - i] = self.read_ram(ram_index);
 			}
 		}
 		
@@ -1186,11 +2348,11 @@ impl Emulator {
 	// F000: Save the next 16 bits to I
 	// NOTE: This command reads 2 opcodes, so we must increment the PC again
 	fn opcode_f000(&mut self) {
-		let higher_byte = self.ram[self.pc as usize] as u16;
-		let lower_byte = self.ram[(self.pc + 1) as usize] as u16;
+		let higher_byte = self.read_ram(self.pc as usize) as u16;
+		let lower_byte = self.read_ram(self.pc as usize + 1) as u16;
 		let op = (higher_byte << 8) | lower_byte;
-		self.i_register = op;
-		self.pc += 2;
+		self.i_register = op as u32;
+		self.pc = self.pc.wrapping_add(2);
 	}
 	// FN01: Select drawing plane(s)
 	fn opcode_fn01(&mut self, n: u8) {
@@ -1199,7 +2361,7 @@ impl Emulator {
 	// F002: Store 16 bytes in audio pattern buffer
 	fn opcode_f002(&mut self) {
 		for i in 0..16 {
-			self.pattern_buffer[i] = self.ram[self.i_register as usize + i];
+			self.pattern_buffer[i] = self.read_ram(self.i_register as usize + i);
 		}
 	}
 	
@@ -1207,6 +2369,240 @@ impl Emulator {
 	fn opcode_fx3a(&mut self, x: u8) {
 		self.pitch = x;
 	}
-	
+
+	// The scroll opcodes came with SUPER-CHIP 1.1, after CHIP-48 and
+	// SUPER-CHIP 1.0
+	fn has_scroll(&self) -> bool {
+		!matches!(self.config.variant, Variant::Chip48 | Variant::SChip10)
+	}
+
+	// Opcodes for the Chip8X
+	fn is_chip8x(&self) -> bool {
+		self.config.variant == Variant::Chip8X
+	}
+
+	// 02A0: Cycle the background color through blue, black, green and red
+	fn opcode_02a0(&mut self) {
+		self.background_color = (self.background_color + 1) % 4;
+		self.mark_screen_dirty();
+	}
+	// 5XY1: Add each nibble of V[y] to the nibble of V[x], keeping 3 bits each
+	fn opcode_5xy1(&mut self, x: u8, y: u8) {
+		let vx = self.v_register[x as usize];
+		let vy = self.v_register[y as usize];
+		let high = ((vx & 0x70) + (vy & 0x70)) & 0x70;
+		let low = ((vx & 0x07) + (vy & 0x07)) & 0x07;
+		self.v_register[x as usize] = high | low;
+	}
+	// BXY0: Set the foreground color of zones to V[x+1]. The low nibbles of
+	// V[x] and V[y] are the first column (8 pixels wide) and row (4 pixels
+	// high), and the high nibbles are how many more follow
+	// BXYN: Set the foreground color of N rows starting at V[y], in the
+	// column that contains V[x], to V[x+1]
+	fn opcode_bxyn(&mut self, x: u8, y: u8, n: u8) {
+		let vx = self.v_register[x as usize] as usize;
+		let vy = self.v_register[y as usize] as usize;
+		let color = self.v_register[(x as usize + 1) & 0xF] & 0x7;
+		let zones_per_row = self.screen_width / CHIP8X_ZONE_WIDTH;
+		let (columns, rows) = if n == 0 {
+			let first_row = 4 * (vy & 0xF);
+			(vx & 0xF..=(vx & 0xF) + (vx >> 4), first_row..first_row + 4 * ((vy >> 4) + 1))
+		} else {
+			let column = vx / CHIP8X_ZONE_WIDTH;
+			(column..=column, vy..vy + n as usize)
+		};
+		for row in rows {
+			for column in columns.clone() {
+				let zone = (column % zones_per_row) + zones_per_row * (row % self.screen_height);
+				self.color_map[zone] = color;
+			}
+		}
+		self.mark_screen_dirty();
+	}
+	// EXF2: Skip if key V[x] of the second keypad is pressed
+	fn opcode_exf2(&mut self, x: u8) {
+		let key = self.v_register[x as usize] & 0xF;
+		if self.keys_2[key as usize] {
+			self.pc = self.pc.wrapping_add(2);
+			self.skip_cycles();
+		}
+	}
+	// EXF5: Skip if key V[x] of the second keypad is not pressed
+	fn opcode_exf5(&mut self, x: u8) {
+		let key = self.v_register[x as usize] & 0xF;
+		if !self.keys_2[key as usize] {
+			self.pc = self.pc.wrapping_add(2);
+			self.skip_cycles();
+		}
+	}
+	// FXF8: Output V[x] to the I/O port, which sets the tone of the sound board
+	fn opcode_fxf8(&mut self, x: u8) {
+		self.port_output = self.v_register[x as usize];
+	}
+	// FXFB: Read V[x] from the I/O port
+	fn opcode_fxfb(&mut self, x: u8) {
+		self.v_register[x as usize] = self.port_input;
+	}
+
+	// Opcodes for the MegaChip
+	fn is_megachip(&self) -> bool {
+		self.config.variant == Variant::MegaChip
+	}
+
+	// 0010: Disable MEGA-CHIP mode
+	fn opcode_0010(&mut self) {
+		self.mega_mode = false;
+		for i in 0..self.num_planes {
+			self.clear_screen(i as usize);
+		}
+		self.mark_screen_dirty();
+	}
+	// 0011: Enable MEGA-CHIP mode
+	fn opcode_0011(&mut self) {
+		self.mega_mode = true;
+		self.clear_mega_screen();
+		self.mega_display.fill(0);
+		self.mark_screen_dirty();
+	}
+	// 00BN: Scroll display up by N pixels
+	fn opcode_00bn(&mut self, n: u8) {
+		if self.mega_mode {
+			self.scroll_mega(0, -(n as isize));
+		} else {
+			self.opcode_00dn(n);
+		}
+	}
+	// 01NN NNNN: Save the 24-bit address NNNNNN to I
+	// NOTE: This command reads 2 opcodes, so we must increment the PC again
+	fn opcode_01nn(&mut self, nn: u16) {
+		let higher_byte = self.read_ram(self.pc as usize) as u32;
+		let lower_byte = self.read_ram(self.pc as usize + 1) as u32;
+		self.i_register = ((nn as u32) << 16) | (higher_byte << 8) | lower_byte;
+		self.pc = self.pc.wrapping_add(2);
+	}
+	// 02NN: Load NN colors of the palette from I. Each color is 4 bytes
+	// (ARGB), and they are stored starting from index 1
+	fn opcode_02nn(&mut self, nn: u16) {
+		for i in 0..nn as usize {
+			let address = self.i_register as usize + 4 * i;
+			let mut color = 0;
+			for byte in 0..4 {
+				color = (color << 8) | self.read_ram(address + byte) as u32;
+			}
+			self.mega_palette[i + 1] = color;
+		}
+	}
+	// 03NN: Set the sprite width to NN (0 means 256)
+	fn opcode_03nn(&mut self, nn: u16) {
+		self.sprite_width = if nn == 0 {256} else {nn as usize};
+	}
+	// 04NN: Set the sprite height to NN (0 means 256)
+	fn opcode_04nn(&mut self, nn: u16) {
+		self.sprite_height = if nn == 0 {256} else {nn as usize};
+	}
+	// 05NN: Set the screen alpha to NN. Only used for fading out, so it is
+	// ignored
+	fn opcode_05nn(&self) {}
+	// 060N: Play the digitized sound at I. The sound starts with a header of
+	// the sample rate (2 bytes), the number of samples (3 bytes) and a
+	// reserved byte
+	fn opcode_060n(&mut self, n: u8) {
+		let address = self.i_register as usize;
+		let header: Vec<u8> = (0..6).map(|i| self.read_ram(address + i)).collect();
+		let sample_rate = ((header[0] as u16) << 8) | header[1] as u16;
+		let length = ((header[2] as usize) << 16) | ((header[3] as usize) << 8) | header[4] as usize;
+		let start = (address + 6).min(self.ram_size);
+		let end = (start + length).min(self.ram_size);
+		self.mega_sound = Some(MegaSound {
+			sample_rate,
+			samples: self.ram[start..end].to_vec(),
+			looping: n == 0,
+		});
+	}
+	// 0700: Stop the digitized sound
+	fn opcode_0700(&mut self) {
+		self.mega_sound = None;
+	}
+	// 080N: Select the blend mode: normal, 25%, 50%, additive or multiply
+	fn opcode_080n(&mut self, n: u8) {
+		self.blend_mode = match n {
+			1 => BlendMode::Alpha25,
+			2 => BlendMode::Alpha50,
+			3 => BlendMode::Add,
+			4 => BlendMode::Multiply,
+			_ => BlendMode::Normal,
+		};
+	}
+	// 09NN: Set the collision color to NN
+	fn opcode_09nn(&mut self, nn: u16) {
+		self.collision_color = nn as u8;
+	}
+}
+
+// Runs a decoded instruction. It gets the opcode for its operands
+type Handler = fn(&mut Emulator, u16);
+
+// The X, Y and N nibbles of an opcode
+fn op_x(op: u16) -> u8 {
+	((op & 0x0F00) >> 8) as u8
+}
+
+fn op_y(op: u16) -> u8 {
+	((op & 0x00F0) >> 4) as u8
+}
+
+fn op_n(op: u16) -> u8 {
+	(op & 0x000F) as u8
+}
+
+// The start of the last 352 bytes of the first 4K of RAM, where the VIP
+// interpreter keeps its stack, its registers and the display
+fn vip_work_area(ram_size: usize) -> usize {
+	ram_size.min(RAM_SIZE) - VIP_WORK_AREA_SIZE
+}
+
+// True if the ROM is a hi-res CHIP-8 program, which starts by jumping to the
+// hi-res interpreter with 1260
+pub fn is_hires_rom(data: &[u8]) -> bool {
+	data.starts_with(&[0x12, 0x60])
+}
+
+// The MEGA-CHIP palette before a program loads its own: color 0 is
+// transparent black and color 255, used for the font, is white
+fn default_mega_palette() -> [u32; 256] {
+	let mut palette = [0xFF00_0000; 256];
+	palette[0] = 0;
+	palette[255] = 0xFFFF_FFFF;
+	palette
+}
+
+// Double every bit of a sprite row, for the 2x2 pixels of the low resolution
+// mode of SUPER-CHIP
+fn double_bits(pixels: u16) -> u32 {
+	let mut doubled = 0;
+	for bit in 0..16 {
+		if pixels & (1 << bit) != 0 {
+			doubled |= 0b11 << (2 * bit);
+		}
+	}
+	doubled
+}
+
+// Mix a MEGA-CHIP sprite color with the color under it, channel by channel
+fn blend(source: u32, destination: u32, mode: BlendMode) -> u32 {
+	let mut result = 0xFF00_0000;
+	for shift in [0, 8, 16] {
+		let s = (source >> shift) & 0xFF;
+		let d = (destination >> shift) & 0xFF;
+		let channel = match mode {
+			BlendMode::Normal => s,
+			BlendMode::Alpha25 => (s + 3 * d) / 4,
+			BlendMode::Alpha50 => (s + d) / 2,
+			BlendMode::Add => (s + d).min(0xFF),
+			BlendMode::Multiply => s * d / 0xFF,
+		};
+		result |= channel << shift;
+	}
+	result
 }
 
@@ -0,0 +1,103 @@
+// Rendering of the screen to a buffer of colors, for frontends that do not
+// want to combine the planes and the palette themselves
+
+use std::fmt;
+
+use crate::{Emulator, Variant, MEGA_SCREEN_WIDTH, MEGA_SCREEN_HEIGHT};
+
+// The order of the 4 bytes of every rendered pixel
+#[derive(Copy, Clone, PartialEq)]
+pub enum PixelFormat {
+	Rgba,
+	Argb,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RenderError {
+	// The buffer cannot hold the screen at the given scale
+	BufferTooSmall { size: usize, needed: usize },
+	// The scale has to be at least 1
+	InvalidScale,
+}
+
+impl fmt::Display for RenderError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			RenderError::BufferTooSmall { size, needed } => {
+				write!(f, "Buffer is too small: {} bytes ({} bytes are needed)", size, needed)
+			}
+			RenderError::InvalidScale => write!(f, "The scale has to be at least 1"),
+		}
+	}
+}
+
+impl std::error::Error for RenderError {}
+
+impl Emulator {
+	// The width and height in pixels of the rendered screen at a scale. In
+	// MEGA-CHIP mode this is the size of the MEGA-CHIP screen
+	pub fn get_render_size(&self, scale: usize) -> (usize, usize) {
+		if self.mega_mode {
+			(MEGA_SCREEN_WIDTH * scale, MEGA_SCREEN_HEIGHT * scale)
+		} else {
+			(self.screen_width * scale, self.screen_height * scale)
+		}
+	}
+
+	// The size in bytes of the buffer render needs at a scale
+	pub fn get_render_buffer_size(&self, scale: usize) -> usize {
+		let (width, height) = self.get_render_size(scale);
+		width * height * 4
+	}
+
+	// Render the screen into the buffer, row by row with 4 bytes per pixel,
+	// every pixel repeated scale times in both directions. Pixels get the
+	// color of the palette (as 0xRRGGBB) at their color index, the one of
+	// get_pixel_index, or of get_color_indices on the CHIP-8X. In MEGA-CHIP
+	// mode the palette is not used, as the screen already holds colors
+	pub fn render(&self, palette: &[u32; 16], scale: usize, format: PixelFormat, buffer: &mut [u8]) -> Result<(), RenderError> {
+		if scale == 0 {
+			return Err(RenderError::InvalidScale);
+		}
+		let needed = self.get_render_buffer_size(scale);
+		if buffer.len() < needed {
+			return Err(RenderError::BufferTooSmall { size: buffer.len(), needed });
+		}
+
+		let (width, height) = self.get_render_size(1);
+		let colors: Vec<u32> = if self.mega_mode {
+			self.mega_display.clone()
+		} else if self.config.variant == Variant::Chip8X {
+			self.get_color_indices().iter().map(|index| palette[*index]).collect()
+		} else {
+			self.get_pixel_indices().iter().map(|index| palette[*index]).collect()
+		};
+
+		let row_size = width * scale * 4;
+		for (y, row) in colors.chunks(width).enumerate().take(height) {
+			let first_line = y * scale * row_size;
+			let line = &mut buffer[first_line..first_line + row_size];
+			for (x, color) in row.iter().enumerate() {
+				let bytes = color_bytes(*color, format);
+				for pixel in line[x * scale * 4..(x + 1) * scale * 4].chunks_exact_mut(4) {
+					pixel.copy_from_slice(&bytes);
+				}
+			}
+			// The other lines of the row are copies of the first one
+			for copy in 1..scale {
+				let start = first_line + copy * row_size;
+				buffer.copy_within(first_line..first_line + row_size, start);
+			}
+		}
+		Ok(())
+	}
+}
+
+// The bytes of an opaque 0xRRGGBB color in the given format
+fn color_bytes(color: u32, format: PixelFormat) -> [u8; 4] {
+	let [_, r, g, b] = color.to_be_bytes();
+	match format {
+		PixelFormat::Rgba => [r, g, b, 0xFF],
+		PixelFormat::Argb => [0xFF, r, g, b],
+	}
+}
@@ -0,0 +1,255 @@
+// Small programs that check how the emulator runs them
+
+use std::time::Duration;
+
+use chip8_core::{DirtyRect, EmuConfig, Emulator, Variant};
+
+fn new_emulator(config: &EmuConfig, rom: &[u8]) -> Emulator {
+	let mut emulator = Emulator::new(config);
+	emulator.load(rom).unwrap();
+	emulator
+}
+
+// Calls itself forever
+const RECURSION: [u8; 2] = [
+	0x22, 0x00, // 0x200: Call 0x200
+];
+
+#[test]
+fn stack_overflow_halts() {
+	let config = EmuConfig::new(Variant::Chip8);
+	let mut emulator = new_emulator(&config, &RECURSION);
+	emulator.run_until_vblank(100);
+	assert!(emulator.is_halted());
+	assert_eq!(emulator.get_instruction_count(), config.stack_depth.unwrap() as u64 + 1);
+}
+
+#[test]
+fn unlimited_stack_has_no_limit() {
+	let mut config = EmuConfig::new(Variant::Chip8);
+	config.stack_depth = None;
+	let mut emulator = new_emulator(&config, &RECURSION);
+	for _ in 0..10 {
+		emulator.run_until_vblank(10000);
+	}
+	assert!(!emulator.is_halted());
+	assert_eq!(emulator.get_instruction_count(), 100000);
+}
+
+#[test]
+fn returning_with_an_empty_stack_halts() {
+	let config = EmuConfig::new(Variant::Chip8);
+	let mut emulator = new_emulator(&config, &[0x00, 0xEE]);
+	emulator.run_until_vblank(100);
+	assert!(emulator.is_halted());
+}
+
+// 5XY1 only exists on CHIP-8X
+const CHIP8X_OPCODE: [u8; 6] = [
+	0x50, 0x11, // 0x200: Add the colors in V0 and V1 on CHIP-8X
+	0x6A, 0x01, // VA = 1
+	0x12, 0x04, // Wait here
+];
+
+#[test]
+fn strict_mode_halts_on_an_opcode_the_variant_does_not_decode() {
+	for cached_interpreter in [false, true] {
+		let mut config = EmuConfig::new(Variant::Chip8);
+		config.strict = true;
+		config.cached_interpreter = cached_interpreter;
+		let mut emulator = new_emulator(&config, &CHIP8X_OPCODE);
+		emulator.run_until_vblank(100);
+		assert!(emulator.is_halted());
+		assert_eq!(emulator.get_invalid_opcodes(), &[(0x200, 0x5011)]);
+		assert_eq!(emulator.get_instruction_count(), 1);
+	}
+}
+
+#[test]
+fn opcodes_the_variant_does_not_decode_are_skipped() {
+	for cached_interpreter in [false, true] {
+		let mut config = EmuConfig::new(Variant::Chip8);
+		config.cached_interpreter = cached_interpreter;
+		let mut emulator = new_emulator(&config, &CHIP8X_OPCODE);
+		emulator.run_until_vblank(100);
+		assert!(!emulator.is_halted());
+		assert!(emulator.is_idle());
+		assert!(emulator.get_invalid_opcodes().is_empty());
+	}
+}
+
+// The HP-48 interpreters before SUPER-CHIP 1.1 lack some of its opcodes
+#[test]
+fn strict_mode_reports_the_opcodes_missing_before_schip_1_1() {
+	let programs: [(Variant, u16); 5] = [
+		(Variant::SChip10, 0x00FB), // Scroll right
+		(Variant::SChip10, 0x00FC), // Scroll left
+		(Variant::SChip10, 0x00C1), // Scroll down
+		(Variant::Chip48, 0x00FB),
+		(Variant::Chip48, 0xF030), // Point I to the big font
+	];
+	for (variant, op) in programs {
+		let mut config = EmuConfig::new(variant);
+		config.strict = true;
+		let mut emulator = new_emulator(&config, &op.to_be_bytes());
+		emulator.run_until_vblank(100);
+		assert!(emulator.is_halted(), "{:04X}", op);
+		assert_eq!(emulator.get_invalid_opcodes(), &[(0x200, op)]);
+	}
+}
+
+// Run the program for a few frames and return the emulator
+fn run(config: &EmuConfig, rom: &[u8]) -> Emulator {
+	let mut emulator = new_emulator(config, rom);
+	for _ in 0..4 {
+		emulator.run_until_vblank(100);
+	}
+	emulator
+}
+
+#[test]
+fn quirk_shifting_shifts_vx_in_place() {
+	let rom = [
+		0x60, 0x01, // V0 = 1
+		0x61, 0x06, // V1 = 6
+		0x80, 0x16, // V0 = V1 >> 1, or V0 >> 1 with the quirk
+	];
+	let mut config = EmuConfig::new(Variant::Chip8);
+	assert_eq!(run(&config, &rom).get_v_registers()[0], 3);
+	config.quirk_shifting = true;
+	assert_eq!(run(&config, &rom).get_v_registers()[0], 0);
+}
+
+#[test]
+fn quirk_jumping_adds_vx() {
+	let mut rom = vec![
+		0x60, 0x00, // V0 = 0
+		0x62, 0x04, // V2 = 4
+		0xB2, 0x10, // Jump to 0x210 + V0, or 0x210 + V2 with the quirk
+	];
+	rom.resize(0x10, 0);
+	rom.extend([
+		0x6A, 0x01, // 0x210: VA = 1
+		0x12, 0x12, // Wait here
+		0x6A, 0x02, // 0x214: VA = 2
+		0x12, 0x16, // Wait here
+	]);
+	let mut config = EmuConfig::new(Variant::Chip8);
+	assert_eq!(run(&config, &rom).get_v_registers()[0xA], 1);
+	config.quirk_jumping = true;
+	assert_eq!(run(&config, &rom).get_v_registers()[0xA], 2);
+}
+
+#[test]
+fn quirk_memory_increments_i() {
+	let rom = [
+		0xA3, 0x00, // I = 0x300
+		0xF1, 0x55, // Store V0 and V1
+	];
+	let mut config = EmuConfig::new(Variant::Chip8);
+	config.quirk_memory = false;
+	assert_eq!(run(&config, &rom).get_i_register(), 0x300);
+	config.quirk_memory = true;
+	assert_eq!(run(&config, &rom).get_i_register(), 0x302);
+	config.quirk_memoryincrementbyx = true;
+	assert_eq!(run(&config, &rom).get_i_register(), 0x301);
+}
+
+#[test]
+fn quirk_vfreset_clears_vf() {
+	let rom = [
+		0x6F, 0x05, // VF = 5
+		0x80, 0x11, // V0 |= V1, and VF = 0 with the quirk
+	];
+	let mut config = EmuConfig::new(Variant::Chip8);
+	config.quirk_vfreset = false;
+	assert_eq!(run(&config, &rom).get_v_registers()[0xF], 5);
+	config.quirk_vfreset = true;
+	assert_eq!(run(&config, &rom).get_v_registers()[0xF], 0);
+}
+
+#[test]
+fn dirty_rect_covers_what_changed() {
+	let rom = [
+		0x60, 0x08, // V0 = 8
+		0x61, 0x04, // V1 = 4
+		0xF2, 0x29, // I = the font digit in V2 (0)
+		0xD0, 0x15, // Draw it at (8, 4)
+		0x12, 0x08, // Wait here
+	];
+	let config = EmuConfig::new(Variant::Chip8);
+	let mut emulator = new_emulator(&config, &rom);
+	// Loading marks the whole screen
+	assert!(emulator.take_dirty().is_some());
+	emulator.run_until_vblank(100);
+	assert_eq!(emulator.take_dirty(), Some(DirtyRect { x: 8, y: 4, width: 4, height: 5 }));
+	emulator.run_until_vblank(100);
+	assert_eq!(emulator.take_dirty(), None);
+}
+
+// Waits for the delay timer, then stops in a loop
+const DELAY_WAIT: [u8; 12] = [
+	0x60, 0x10, // V0 = 16
+	0xF0, 0x15, // Delay timer = V0
+	0xF0, 0x07, // 0x204: V0 = delay timer
+	0x30, 0x00, // Skip if V0 == 0
+	0x12, 0x04, // Jump to 0x204
+	0x12, 0x0A, // 0x20A: Wait here
+];
+
+// Skipping the rounds of idle loops has to end up in the same state as
+// running them
+#[test]
+fn idle_loops_are_skipped_without_changing_the_result() {
+	let config = EmuConfig::new(Variant::Chip8);
+	let mut skipping = new_emulator(&config, &DELAY_WAIT);
+	let mut ticking = new_emulator(&config, &DELAY_WAIT);
+	for frame in 0..30 {
+		skipping.run_until_vblank(100);
+		for i in 0..100 {
+			ticking.tick(i == 0);
+		}
+		ticking.tick_timers();
+		assert!(skipping.is_idle(), "frame {}", frame);
+		assert_eq!(skipping.get_pc(), ticking.get_pc(), "frame {}", frame);
+		assert_eq!(skipping.get_v_registers(), ticking.get_v_registers(), "frame {}", frame);
+		assert_eq!(skipping.get_instruction_count(), ticking.get_instruction_count(), "frame {}", frame);
+	}
+	assert_eq!(skipping.get_pc(), 0x20A);
+}
+
+// The time left over is kept for the next call, but a host that falls far
+// behind does not get all the frames it missed
+#[test]
+fn run_for_runs_60_frames_a_second() {
+	let config = EmuConfig::new(Variant::Chip8);
+	let mut emulator = new_emulator(&config, &DELAY_WAIT);
+	assert_eq!(emulator.run_for(Duration::from_millis(10), 600), 0);
+	assert_eq!(emulator.run_for(Duration::from_millis(10), 600), 1);
+	assert_eq!(emulator.get_instruction_count(), 10);
+	assert_eq!(emulator.run_for(Duration::from_millis(50), 600), 3);
+	assert_eq!(emulator.get_instruction_count(), 40);
+	assert_eq!(emulator.run_for(Duration::from_secs(1), 600), 6);
+	assert_eq!(emulator.get_instruction_count(), 100);
+}
+
+// A machine code subroutine (0NNN on the VIP) that stores a byte at I
+#[test]
+fn machine_code_runs_on_the_vip() {
+	let mut rom = vec![
+		0xA4, 0x00, // I = 0x400
+		0x03, 0x00, // Call the machine code at 0x300
+		0x12, 0x04, // Wait here
+	];
+	rom.resize(0x100, 0);
+	rom.extend([
+		0xF8, 0x42, // 0x300: LDI 0x42
+		0x5A,       // STR RA: store it at I
+		0x1A,       // INC RA: I += 1
+		0xD4,       // SEP R4: return to CHIP-8
+	]);
+	let emulator = run(&EmuConfig::new(Variant::Chip8), &rom);
+	assert_eq!(emulator.get_ram()[0x400], 0x42);
+	assert_eq!(emulator.get_i_register(), 0x401);
+	assert_eq!(emulator.get_pc(), 0x204);
+}
@@ -0,0 +1,129 @@
+// The plain and the cached interpreter have to run programs the same way
+
+use std::path::Path;
+
+use chip8_core::{EmuConfig, Emulator, Variant};
+
+fn new_emulator(variant: Variant, cached_interpreter: bool, rom: &[u8]) -> Emulator {
+	let mut config = EmuConfig::new(variant);
+	config.cached_interpreter = cached_interpreter;
+	let mut emulator = Emulator::new(&config);
+	emulator.load(rom).unwrap();
+	emulator
+}
+
+// Run the program with both interpreters side by side, pressing key 5 now
+// and then, and check that they are in the same state after every frame
+fn run_both(variant: Variant, rom: &[u8], frames: u32, ticks_per_frame: u32) {
+	let mut plain = new_emulator(variant, false, rom);
+	let mut cached = new_emulator(variant, true, rom);
+	for frame in 0..frames {
+		for emulator in [&mut plain, &mut cached] {
+			if frame % 50 == 10 {
+				emulator.register_keypress(5, true);
+			}
+			if frame % 50 == 20 {
+				emulator.register_keypress(5, false);
+			}
+			emulator.run_until_vblank(ticks_per_frame);
+		}
+		assert_eq!(plain.get_pc(), cached.get_pc(), "frame {}", frame);
+		assert_eq!(plain.get_i_register(), cached.get_i_register(), "frame {}", frame);
+		assert_eq!(plain.get_v_registers(), cached.get_v_registers(), "frame {}", frame);
+		assert!(plain.get_ram() == cached.get_ram(), "frame {}: the RAM differs", frame);
+		assert!(plain.get_screen_planes() == cached.get_screen_planes(), "frame {}: the screen differs", frame);
+		assert_eq!(plain.get_instruction_count(), cached.get_instruction_count(), "frame {}", frame);
+		assert_eq!(plain.is_halted(), cached.is_halted(), "frame {}", frame);
+	}
+}
+
+// The games in c8games that do not use random numbers (CXNN) before the end
+// of the test, which would make the interpreters go separate ways
+#[test]
+fn games_run_the_same() {
+	let games = Path::new(env!("CARGO_MANIFEST_DIR")).join("../c8games");
+	let names = [
+		"15PUZZLE", "BLINKY", "CONNECT4", "GUESS", "INVADERS", "KALEID",
+		"MISSILE", "SYZYGY", "TICTAC", "VBRIX", "VERS",
+	];
+	for name in names {
+		let Ok(rom) = std::fs::read(games.join(name)) else {
+			continue;
+		};
+		for variant in [Variant::Chip8, Variant::SChip, Variant::XOChip] {
+			run_both(variant, &rom, 300, 20);
+		}
+	}
+}
+
+// A loop that stores a new instruction over the one it runs next. The
+// cached interpreter has to forget the old one
+#[test]
+fn self_modifying_code_runs_the_same() {
+	let rom = [
+		0x60, 0x7A, // V0 = 0x7A
+		0x61, 0x00, // V1 = 0
+		0xA2, 0x0C, // 0x204: I = 0x20C
+		0x71, 0x01, // V1 += 1
+		0xF1, 0x55, // Store V0 and V1 at 0x20C
+		0x7B, 0x01, // VB += 1
+		0x00, 0x00, // 0x20C: Rewritten to VA += V1
+		0x12, 0x04, // Jump to 0x204
+	];
+	run_both(Variant::Chip8, &rom, 60, 100);
+}
+
+// A machine code subroutine (0NNN on the VIP) that writes over the CHIP-8
+// program, which runs it again and again
+#[test]
+fn machine_code_runs_the_same() {
+	let mut rom = vec![
+		0xA2, 0x0A, // 0x200: I = 0x20A
+		0x03, 0x00, // Call the machine code at 0x300
+		0x7A, 0x01, // VA += 1
+		0x12, 0x0A, // Jump to 0x20A
+		0x00, 0x00,
+		0x6B, 0x00, // 0x20A: VB = the byte the machine code stores
+		0x12, 0x00, // Jump to 0x200
+	];
+	rom.resize(0x100, 0);
+	rom.extend([
+		0xF8, 0x6B, // 0x300: LDI 0x6B
+		0x5A,       // STR RA: store it at I
+		0x1A,       // INC RA: I += 1
+		0x0A,       // LDN RA: D = M(I)
+		0xFC, 0x01, // ADI 1
+		0x5A,       // STR RA: the operand of 6B00 goes up by 1
+		0xD4,       // SEP R4: return to CHIP-8
+	]);
+	run_both(Variant::Chip8, &rom, 60, 100);
+}
+
+// A program that runs off the end of the 64K of RAM: the PC wraps around to
+// 0x0000, where the program stored a jump back to a loop that waits forever
+#[test]
+fn pc_wraps_around_the_end_of_ram() {
+	let mut rom = vec![
+		0xA0, 0x00, // I = 0x000
+		0x60, 0x63, // V0 = 0x63
+		0x61, 0x01, // V1 = 0x01
+		0x62, 0x12, // V2 = 0x12
+		0x63, 0x20, // V3 = 0x20
+		0xF3, 0x55, // Store 6301 1220 (V3 = 1, jump to 0x220) at 0x000
+		0x63, 0x00, // V3 = 0, then run the empty RAM up to 0xFFFF
+	];
+	rom.resize(0x20, 0);
+	rom.extend([
+		0x43, 0x01, // 0x220: Skip if V3 != 1
+		0x12, 0x22, // Wait here once the PC wrapped around
+	]);
+
+	for cached_interpreter in [false, true] {
+		let mut emulator = new_emulator(Variant::XOChip, cached_interpreter, &rom);
+		for _ in 0..4 {
+			emulator.run_until_vblank(20000);
+		}
+		assert!(emulator.is_idle(), "cached_interpreter: {}", cached_interpreter);
+	}
+	run_both(Variant::XOChip, &rom, 4, 20000);
+}
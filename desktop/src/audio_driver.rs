@@ -2,6 +2,7 @@
 
 use chip8_core::PATTERN_BUFFER_SIZE;
 use chip8_core::Variant;
+use chip8_core::MegaSound;
 
 use sdl2::audio::{AudioCallback, AudioSpecDesired, AudioDevice};
 use sdl2::AudioSubsystem;
@@ -63,17 +64,54 @@ impl AudioCallback for PatternWave {
 	}
 }
 
+// Plays the digitized sounds of MEGA-CHIP, resampled to the rate of the
+// audio device
+struct SampleWave {
+	samples: Vec<u8>,
+	position: f32,
+	step: f32, // How many samples to move forward for each output sample
+	looping: bool,
+	device_frequency: i32,
+}
+
+// Audio callback function used by SDL
+impl AudioCallback for SampleWave {
+	type Channel = u8;
+
+	fn callback(&mut self, out: &mut [u8]) {
+		for x in out.iter_mut() {
+			if self.position as usize >= self.samples.len() {
+				if self.looping && !self.samples.is_empty() {
+					self.position = 0.0;
+				} else {
+					// Silence for unsigned 8-bit samples
+					*x = 128;
+					continue;
+				}
+			}
+			*x = self.samples[self.position as usize];
+			self.position += self.step;
+		}
+	}
+}
+
 pub struct AudioDriver {
 	desired_spec: AudioSpecDesired,
 	chip8_audio_device: Option <AudioDevice<SquareWave>>,
 	xo_audio_device: Option <AudioDevice<PatternWave>>,
+	mega_audio_device: Option <AudioDevice<SampleWave>>,
 
 	play_sound: bool, // whether sound is being played at this moment
 
 	pub pattern_buffer: [u8; PATTERN_BUFFER_SIZE],
 	pub frequency: i32,
+	pub mega_sound: Option<MegaSound>,
 
 	mute: bool,
+	// Silenced while paused or fast-forwarding, on top of the mute
+	silenced: bool,
+	// On the CHIP-8X the sound board sets the tone of the square wave
+	tone_follows_frequency: bool,
 }
 
 impl AudioDriver {
@@ -97,8 +135,13 @@ impl AudioDriver {
 			None
 		} else {
 			let device = audio_subsystem.open_playback(None, &spec, |_| {
+				let tone = if *variant == Variant::Chip8X {
+					desired_frequency as f32
+				} else {
+					440.0
+				};
 				SquareWave {
-					phase_inc: 440.0 / spec.freq.unwrap() as f32,
+					phase_inc: tone / spec.freq.unwrap() as f32,
 					phase: 0.0,
 					volume: 0.25
 				}
@@ -120,16 +163,36 @@ impl AudioDriver {
 			None
 		};
 		
+		let mega_device = if *variant == Variant::MegaChip {
+			let device = audio_subsystem.open_playback(None, &spec, |obtained_spec| {
+				SampleWave {
+					samples: Vec::new(),
+					position: 0.0,
+					step: 0.0,
+					looping: false,
+					device_frequency: obtained_spec.freq,
+				}
+			}).unwrap();
+			device.pause();
+			Some(device)
+		} else {
+			None
+		};
+		
 		AudioDriver {
 			desired_spec: spec,
 			chip8_audio_device: chip8_device,
 			xo_audio_device: xo_device,
+			mega_audio_device: mega_device,
 
 			play_sound: false,
 			
 			pattern_buffer: buffer,
 			frequency: desired_frequency,
-			mute: is_mute
+			mega_sound: None,
+			mute: is_mute,
+			silenced: false,
+			tone_follows_frequency: *variant == Variant::Chip8X,
 		}
 	}
 
@@ -144,11 +207,40 @@ impl AudioDriver {
 		else {
 			self.mute = !self.mute;
 		}
+		if let Some(ref device) = self.mega_audio_device {
+			if self.mute || self.mega_sound.is_none() {
+				device.pause();
+			} else {
+				device.resume();
+			}
+		}
 	}
 	
+	// Stop all sounds while the emulator is paused or fast-forwarding, and
+	// bring them back afterwards. The sounds are played at the rate of the
+	// audio device, so when they are not silenced they keep their pitch
+	// whatever the speed of the emulator
+	pub fn set_silenced(&mut self, silenced: bool) {
+		if silenced == self.silenced {
+			return;
+		}
+		self.silenced = silenced;
+		if silenced {
+			self.handle_audio(false);
+		}
+		if let Some(ref device) = self.mega_audio_device {
+			if silenced || self.mute || self.mega_sound.is_none() {
+				device.pause();
+			} else {
+				device.resume();
+			}
+		}
+	}
+
 	// Call this once per frame to play the correct sound
 	pub fn handle_audio(&mut self, beep: bool) {
 		if self.mute {return;}
+		let beep = beep && !self.silenced;
 		if !self.play_sound && beep {
 			self.play_sound = true;
 			match self.chip8_audio_device {
@@ -178,8 +270,39 @@ impl AudioDriver {
 		}
 	}
 
+	// Start playing the given MEGA-CHIP sound from its beginning, or stop
+	// playing if there is none
+	pub fn update_mega_sound(&mut self, sound: Option<&MegaSound>) {
+		self.mega_sound = sound.cloned();
+		if let Some(ref mut device) = self.mega_audio_device {
+			let mut lock_guard = device.lock();
+			match sound {
+				Some(sound) => {
+					lock_guard.samples = sound.samples.clone();
+					lock_guard.position = 0.0;
+					lock_guard.step = sound.sample_rate as f32 / lock_guard.device_frequency as f32;
+					lock_guard.looping = sound.looping;
+				}
+				None => lock_guard.samples.clear(),
+			}
+			drop(lock_guard);
+			if sound.is_some() && !self.mute && !self.silenced {
+				device.resume();
+			} else {
+				device.pause();
+			}
+		}
+	}
+
 	// Update the frequency with the one given
 	pub fn update_frequency(&mut self, audio_subsystem: &AudioSubsystem, new_frequency: i32) {
+		self.frequency = new_frequency;
+		if self.tone_follows_frequency {
+			if let Some(ref mut device) = self.chip8_audio_device {
+				let device_frequency = device.spec().freq;
+				device.lock().phase_inc = new_frequency as f32 / device_frequency as f32;
+			}
+		}
 		match self.xo_audio_device {
 			Some(ref mut device) => {
 				let lock_guard = device.lock();
@@ -4,9 +4,85 @@ use clap::ValueEnum;
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 pub enum CLIVariant {
 	Chip8,
+	Chip8Hires,
+	Chip10,
+	Chip8x,
+	Chip48,
+	Schip10,
 	Schip,
 	SchipLegacy,
 	XOChip,
+	Megachip,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum CLIFont {
+	Vip,
+	Eti660,
+	Dream6800,
+	Schip10,
+	Schip11,
+	Octo,
+	Fishnchips,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum CLIStackDepth {
+	#[value(name = "12")]
+	Twelve,
+	#[value(name = "16")]
+	Sixteen,
+	Unlimited,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum CLIFastForwardAudio {
+	// No sound while fast-forwarding
+	Mute,
+	// Keep playing the sound at its normal pitch
+	Pitch,
+}
+
+// The speed of the emulator: a number of instructions per second, or the
+// speed of the COSMAC VIP with its timing
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Speed {
+	Ips(u32),
+	Vip,
+}
+
+impl Speed {
+	// The speeds that can be picked while running
+	pub const PRESETS: [Speed; 7] = [
+		Speed::Vip,
+		Speed::Ips(500),
+		Speed::Ips(700),
+		Speed::Ips(1000),
+		Speed::Ips(1200),
+		Speed::Ips(30000),
+		Speed::Ips(60000),
+	];
+
+	// The first preset that is faster than this speed, or the slowest one
+	pub fn next_preset(&self) -> Speed {
+		let index = Speed::PRESETS.iter().position(|preset| *preset == *self);
+		match (self, index) {
+			(_, Some(i)) => Speed::PRESETS[(i + 1) % Speed::PRESETS.len()],
+			(Speed::Ips(ips), None) => *Speed::PRESETS.iter()
+				.find(|preset| matches!(preset, Speed::Ips(p) if p > ips))
+				.unwrap_or(&Speed::PRESETS[0]),
+			(Speed::Vip, None) => Speed::PRESETS[0],
+		}
+	}
+
+	// The instructions per second run_for is given. The VIP timing decides
+	// by itself
+	pub fn get_ips(&self) -> u32 {
+		match self {
+			Speed::Ips(ips) => *ips,
+			Speed::Vip => 0,
+		}
+	}
 }
 
 #[derive(Parser)]
@@ -14,12 +90,24 @@ pub enum CLIVariant {
 pub struct Args {
 
 	// The file name we read, must be given as an argument
+	#[arg(help = "The ROM to run, a .zip archive holding it, or a .gif cartridge exported by this emulator (Octo cartridges holding source code cannot be opened)")]
 	pub file_name: String,
 
+	// Which file to read when the file is a .zip archive
+	#[arg(long, help = "The file to load when reading from a .zip archive")]
+	pub zip_entry: Option<String>,
+
+	#[arg(long, help = "List the files inside a .zip archive and exit")]
+	pub list_entries: bool,
+
 	// Emulation Settings
 	#[arg(short, long, value_enum, default_value_t = CLIVariant::Schip)]
 	pub variant: CLIVariant,
 
+	// Octo options file with the quirks, tick rate and colors to use
+	#[arg(long, help = "Load the quirks, tick rate and colors from an Octo options file (JSON)")]
+	pub options: Option<String>,
+
 	#[arg(long)]
 	pub quirk_legacyscroll: bool,
 	#[arg(long)]
@@ -36,17 +124,78 @@ pub struct Args {
 	pub quirk_jumping: bool,
 	#[arg(long)]
 	pub quirk_clipcollision: bool,
+	#[arg(long)]
+	pub quirk_memoryincrementbyx: bool,
+	#[arg(long)]
+	pub quirk_keypress: bool,
+	#[arg(long)]
+	pub quirk_indexoverflow: bool,
+	#[arg(long)]
+	pub quirk_index12bit: bool,
+	#[arg(long)]
+	pub quirk_lowressprite: bool,
+	#[arg(long)]
+	pub quirk_resclear: bool,
+	#[arg(long)]
+	pub quirk_vforder: bool,
+
+	// How many addresses fit in the stack
+	#[arg(long, value_enum, help = "How many addresses fit in the stack before the program halts [defaults: chip8 = 12, others = 16]")]
+	pub stack_depth: Option<CLIStackDepth>,
+
+	// Memory layout
+	#[arg(long, value_parser = parse_address, help = "Address the ROM is loaded to and execution starts from, e.g. 0x600 for ETI-660 [default: 0x200]")]
+	pub load_address: Option<u16>,
+	#[arg(long, value_parser = parse_size, help = "Size of the RAM in bytes [defaults: chip8 = 4096, s-chip = 4096, xo-chip = 65536, megachip = 16777216]")]
+	pub ram_size: Option<usize>,
+	#[arg(long, value_parser = parse_address, help = "Address of the small font used by FX29 [default: 0x000]")]
+	pub font_address: Option<u16>,
+	#[arg(long, value_parser = parse_address, help = "Address of the big font used by FX30 [default: 0x050]")]
+	pub big_font_address: Option<u16>,
+
+	// Fonts
+	#[arg(long, value_enum, help = "The font to use [defaults: chip8 = vip, s-chip = schip11, xo-chip = octo]")]
+	pub font: Option<CLIFont>,
+	#[arg(long, help = "Load a custom font: 80 bytes for the small font, optionally followed by 100 or 160 bytes for the big font")]
+	pub font_file: Option<String>,
+	
+	// Run instructions for as long as they took on the COSMAC VIP, instead of
+	// a number of ticks per frame
+	#[arg(long, help = "Time instructions like the COSMAC VIP did, instead of running a fixed number of ticks per frame")]
+	pub vip_timing: bool,
+
+	// Keep the stack and the display in RAM like the COSMAC VIP
+	#[arg(long, help = "Keep the stack at 0xEA0 and the display at 0xF00 in RAM like the COSMAC VIP, for 64x32 variants")]
+	pub vip_memory_map: bool,
+
+	// Report the instructions that do not exist on the variant
+	#[arg(long, help = "Warn about instructions that do not exist on the selected variant, with their addresses")]
+	pub strict: bool,
+
+	// Decode instructions once instead of every time they run
+	#[arg(long, help = "Decode instructions once and cache them until the program writes over them, for high speeds")]
+	pub cached_interpreter: bool,
 	
 	// Display settings
 	// How many cycles are executed per frame
 	// Note: This is not actually related to emulation
-	#[arg(short, long, default_value_t = 20, help = "Override the ticks per frame. [Defaults: chip8 = 15, s-chip = 20, xo-chip = 500]")]
+	#[arg(short, long, default_value_t = 20, help = "Override the ticks per frame. [Defaults: chip8 = 15, s-chip = 20, xo-chip = 500, megachip = 1000]")]
 	pub ticks_per_frame: u32,
 
+	// The speed in instructions per second, instead of ticks per frame
+	#[arg(long, value_parser = parse_speed, help = "The speed in instructions per second (e.g. 700), or \"vip\" for the timing of the COSMAC VIP. Overrides the ticks per frame")]
+	pub ips: Option<Speed>,
+
 	// The multiplier by which we scale the display
 	#[arg(short, long, default_value_t = 10, help = "The multiplier by which we scale the display")]
 	pub scale: u8,
 
+	// How much faster fast-forwarding runs
+	#[arg(long, default_value_t = 4, help = "How many times faster fast-forwarding runs, 0 for as fast as possible")]
+	pub fast_forward: u32,
+	#[arg(long, value_enum, default_value_t = CLIFastForwardAudio::Mute, help = "Mute the sound while fast-forwarding, or keep it at its normal pitch")]
+	pub fast_forward_audio: CLIFastForwardAudio,
+
 	// Whether we want to turn fps capping off
 	#[arg(long, help = "Turn off capping the framerate at 60 fps")]
 	pub fpscap_off: bool,
@@ -61,9 +210,15 @@ impl Args {
 	pub fn get_ticks_per_frame(&self) -> u32 {
 		let mut ticks = match self.variant {
 			CLIVariant::Chip8 => 15,
+			CLIVariant::Chip8Hires => 15,
+			CLIVariant::Chip10 => 15,
+			CLIVariant::Chip8x => 15,
+			CLIVariant::Chip48 => 15,
+			CLIVariant::Schip10 => 20,
 			CLIVariant::Schip => 20,
 			CLIVariant::SchipLegacy => 20,
 			CLIVariant::XOChip => 500,
+			CLIVariant::Megachip => 1000,
 		};
 
 		if self.ticks_per_frame != 15 {
@@ -76,68 +231,87 @@ impl Args {
 	pub fn get_variant(&self) -> chip8_core::Variant {
 		match self.variant {
 			CLIVariant::Chip8 => chip8_core::Variant::Chip8,
+			CLIVariant::Chip8Hires => chip8_core::Variant::Chip8HiRes,
+			CLIVariant::Chip10 => chip8_core::Variant::Chip10,
+			CLIVariant::Chip8x => chip8_core::Variant::Chip8X,
+			CLIVariant::Chip48 => chip8_core::Variant::Chip48,
+			CLIVariant::Schip10 => chip8_core::Variant::SChip10,
 			CLIVariant::Schip => chip8_core::Variant::SChip,
 			CLIVariant::SchipLegacy => chip8_core::Variant::SChip,
 			CLIVariant::XOChip => chip8_core::Variant::XOChip,
+			CLIVariant::Megachip => chip8_core::Variant::MegaChip,
 		}
 	}
-	
+
 	// Returns an emulation config from the arguments given
 	pub fn get_emuconfig(&self) -> chip8_core::EmuConfig {
-		let mut emu_config = match self.variant {
-			CLIVariant::Chip8 => {
-				chip8_core::EmuConfig {
-					variant: chip8_core::Variant::Chip8,
-					quirk_legacyscroll: false,
-					quirk_vfreset: true,
-					quirk_memory: true,
-					quirk_displaywait: false,
-					quirk_clipping: true,
-					quirk_shifting: false,
-					quirk_jumping: false,
-					quirk_clipcollision: false,
-				}
-			}
-			CLIVariant::Schip => {
-				chip8_core::EmuConfig {
-					variant: chip8_core::Variant::SChip,
-					quirk_legacyscroll: false,
-					quirk_vfreset: false,
-					quirk_memory: false,
-					quirk_displaywait: false,
-					quirk_clipping: true,
-					quirk_shifting: true,
-					quirk_jumping: true,
-					quirk_clipcollision: false,
-				}
-			}
-			CLIVariant::SchipLegacy => {
-				chip8_core::EmuConfig {
-					variant: chip8_core::Variant::SChip,
-					quirk_legacyscroll: true,
-					quirk_vfreset: false,
-					quirk_memory: false,
-					quirk_displaywait: true,
-					quirk_clipping: true,
-					quirk_shifting: true,
-					quirk_jumping: true,
-					quirk_clipcollision: false,
-				}
-			}
-			CLIVariant::XOChip => {
-				chip8_core::EmuConfig {
-					variant: chip8_core::Variant::XOChip,
-					quirk_legacyscroll: false,
-					quirk_vfreset: false,
-					quirk_memory: true,
-					quirk_displaywait: false,
-					quirk_clipping: false,
-					quirk_shifting: false,
-					quirk_jumping: false,
-					quirk_clipcollision: false,
-				}
-			}
-		};
+		let mut emu_config = self.get_platform_emuconfig();
+		self.apply_overrides(&mut emu_config);
+		emu_config
+	}
+
+	// Returns the config of the selected variant, before the overrides
+	pub fn get_platform_emuconfig(&self) -> chip8_core::EmuConfig {
+		let mut emu_config = chip8_core::EmuConfig::new(self.get_variant());
+		// The legacy SUPER-CHIP behaves like the original one on the HP-48
+		if self.variant == CLIVariant::SchipLegacy {
+			emu_config.quirk_legacyscroll = true;
+			emu_config.quirk_displaywait = true;
+		}
+		emu_config
+	}
+
+	// Apply the quirk toggles, the memory layout and the font that were
+	// given as arguments on an emulation config
+	pub fn apply_overrides(&self, emu_config: &mut chip8_core::EmuConfig) {
+		if let Some(address) = self.load_address {
+			emu_config.load_address = address;
+		}
+		if let Some(size) = self.ram_size {
+			emu_config.ram_size = size;
+		}
+		if let Some(address) = self.font_address {
+			emu_config.font_address = address;
+		}
+		if let Some(address) = self.big_font_address {
+			emu_config.big_font_address = address;
+		}
+		if let Some(font) = self.font {
+			emu_config.font_style = match font {
+				CLIFont::Vip => chip8_core::FontStyle::Vip,
+				CLIFont::Eti660 => chip8_core::FontStyle::Eti660,
+				CLIFont::Dream6800 => chip8_core::FontStyle::Dream6800,
+				CLIFont::Schip10 => chip8_core::FontStyle::SChip10,
+				CLIFont::Schip11 => chip8_core::FontStyle::SChip11,
+				CLIFont::Octo => chip8_core::FontStyle::Octo,
+				CLIFont::Fishnchips => chip8_core::FontStyle::FishNChips,
+			};
+		}
+
+		if let Some(depth) = self.stack_depth {
+			emu_config.stack_depth = match depth {
+				CLIStackDepth::Twelve => Some(12),
+				CLIStackDepth::Sixteen => Some(16),
+				CLIStackDepth::Unlimited => None,
+			};
+		}
+
+		if self.vip_timing {
+			emu_config.vip_timing = true;
+		}
+		if self.vip_memory_map {
+			emu_config.vip_memory_map = true;
+		}
+		if self.strict {
+			emu_config.strict = true;
+		}
+		if self.cached_interpreter {
+			emu_config.cached_interpreter = true;
+		}
+		if self.ips == Some(Speed::Vip) {
+			emu_config.vip_timing = true;
+		}
+
 		if self.quirk_vfreset  {
 			emu_config.quirk_vfreset = !emu_config.quirk_vfreset;
 		}
@@ -159,6 +333,51 @@ impl Args {
 		if self.quirk_clipcollision  {
 			emu_config.quirk_clipcollision = !emu_config.quirk_clipcollision;
 		}
-		emu_config
+		if self.quirk_memoryincrementbyx {
+			emu_config.quirk_memoryincrementbyx = !emu_config.quirk_memoryincrementbyx;
+		}
+		if self.quirk_keypress {
+			emu_config.quirk_keypress = !emu_config.quirk_keypress;
+		}
+		if self.quirk_indexoverflow {
+			emu_config.quirk_indexoverflow = !emu_config.quirk_indexoverflow;
+		}
+		if self.quirk_index12bit {
+			emu_config.quirk_index12bit = !emu_config.quirk_index12bit;
+		}
+		if self.quirk_lowressprite {
+			emu_config.quirk_lowressprite = !emu_config.quirk_lowressprite;
+		}
+		if self.quirk_resclear {
+			emu_config.quirk_resclear = !emu_config.quirk_resclear;
+		}
+		if self.quirk_vforder {
+			emu_config.quirk_vforder = !emu_config.quirk_vforder;
+		}
+	}
+}
+
+// Parse a speed given in instructions per second, or "vip"
+fn parse_speed(arg: &str) -> Result<Speed, String> {
+	if arg.eq_ignore_ascii_case("vip") {
+		return Ok(Speed::Vip);
+	}
+	match arg.parse::<u32>() {
+		Ok(ips) if ips > 0 => Ok(Speed::Ips(ips)),
+		_ => Err(format!("{} is not a valid speed", arg)),
 	}
 }
+
+// Parse an address given either in hex (0x600) or in decimal
+fn parse_address(arg: &str) -> Result<u16, String> {
+	let value = parse_size(arg)?;
+	u16::try_from(value).map_err(|_| format!("{} is not a 16-bit address", arg))
+}
+
+// Parse a number given either in hex (0x1000) or in decimal
+fn parse_size(arg: &str) -> Result<usize, String> {
+	match arg.strip_prefix("0x").or(arg.strip_prefix("0X")) {
+		Some(hex) => usize::from_str_radix(hex, 16),
+		None => arg.parse::<usize>(),
+	}.map_err(|_| format!("{} is not a valid number", arg))
+}
@@ -1,11 +1,12 @@
 mod video_driver;
 mod audio_driver;
 mod cli;
+mod octo;
+mod rom;
 
-use std::fs::File;
-use std::io::Read;
+use std::path::Path;
 use std::time::Duration;
-// use std::time::Instant;
+use std::time::Instant;
 
 use clap::Parser;
 
@@ -18,39 +19,125 @@ use spin_sleep; // More accurate than thread::sleep
 use audio_driver::AudioDriver;
 use video_driver::VideoDriver;
 use video_driver::get_all_palettes;
+use video_driver::get_chip8x_palette;
 use chip8_core::*;
+use cli::Speed;
+use cli::CLIFastForwardAudio;
+
+// How long fast-forwarding as fast as possible runs frames for on every loop,
+// leaving the rest of the 60 Hz frame for drawing
+const FAST_FORWARD_BUDGET: Duration = Duration::from_millis(12);
 
 fn main() {
 	let args = cli::Args::parse();
+	if args.list_entries {
+		match rom::list_archive(&args.file_name) {
+			Ok(entries) => entries.iter().for_each(|entry| println!("{}", entry)),
+			Err(e) => eprintln!("{}", e),
+		}
+		return;
+	}
 
 	let sdl_context = sdl2::init().unwrap();
 	let video_subsystem = sdl_context.video().unwrap();
 
-	let selected_variant = args.get_variant();
-	let screen_width = match selected_variant {
-		Variant::Chip8 => 64,
-		Variant::SChip => 128,
-		Variant::XOChip => 128,
+	// Get settings for the emulator and the ROM. Octo cartridges and Octo
+	// options files bring their own settings, otherwise they come from the
+	// arguments
+	let mut emu_config = args.get_emuconfig();
+	let mut ticks_per_frame = args.get_ticks_per_frame();
+	let mut octo_options = None;
+	let (rom_name, file_data) = match rom::read_rom(&args.file_name, args.zip_entry.as_deref()) {
+		Ok(rom) => rom,
+		Err(e) => {
+			eprintln!("{}", e);
+			std::process::exit(1);
+		}
 	};
-	let screen_height = match selected_variant {
-		Variant::Chip8 => 32,
-		Variant::SChip => 64,
-		Variant::XOChip => 64,
-	};			
+	// Files exported from a ROM inside an archive go next to the archive
+	let rom_path = Path::new(&args.file_name).with_file_name(Path::new(&rom_name).file_name().unwrap_or_default());
+	let rom_path = rom_path.to_string_lossy().to_string();
+	let data_buffer = if octo::is_cartridge(&rom_name) {
+		let cartridge = match octo::load_cartridge(&file_data) {
+			Ok(cartridge) => cartridge,
+			Err(e) => {
+				eprintln!("{}", e);
+				std::process::exit(1);
+			}
+		};
+		octo_options = Some(cartridge.options);
+		cartridge.rom
+	} else {
+		file_data
+	};
+	if let Some(options_file) = &args.options {
+		match octo::load_options(options_file) {
+			Ok(options) => octo_options = Some(options),
+			Err(e) => {
+				eprintln!("{}", e);
+				std::process::exit(1);
+			}
+		}
+	}
+	// Only the settings the options contain replace the ones of the variant,
+	// and the arguments still override them
+	if let Some(options) = &octo_options {
+		emu_config = args.get_platform_emuconfig();
+		options.apply_to(&mut emu_config);
+		args.apply_overrides(&mut emu_config);
+		if let Some(tickrate) = options.tickrate {
+			ticks_per_frame = tickrate;
+		}
+	}
+
+	// The speed in instructions per second, unless it is the VIP timing
+	let mut speed = match args.ips {
+		_ if emu_config.vip_timing => Speed::Vip,
+		Some(speed) => speed,
+		None => Speed::Ips(ticks_per_frame * 60),
+	};
+
+	// Hi-res programs are recognized by their first opcode
+	if emu_config.variant == Variant::Chip8 && is_hires_rom(&data_buffer) {
+		emu_config.variant = Variant::Chip8HiRes;
+	}
+
+	if let Err(e) = emu_config.check_memory_layout() {
+		eprintln!("{}", e);
+		std::process::exit(1);
+	}
+
+	let selected_variant = emu_config.variant;
+	let screen_width = selected_variant.screen_width() as u32;
+	let screen_height = selected_variant.screen_height() as u32;
 
 	// Get all available palettes from the config
-	let palettes = get_all_palettes();	
+	let mut palettes = get_all_palettes();	
+	if let Some(options) = &octo_options {
+		palettes.insert(0, options.get_palette());
+	}
+	if selected_variant == Variant::Chip8X {
+		palettes.insert(0, get_chip8x_palette());
+	}
 	let mut video_driver = VideoDriver::new(&video_subsystem, screen_width, screen_height, palettes, args.scale as u32);
+	if selected_variant == Variant::MegaChip {
+		video_driver.enable_mega_screen();
+	}
 
-	// Get settings for the emulator and create an object
-	let emu_config = args.get_emuconfig();
-	let mut ticks_per_frame = args.get_ticks_per_frame();
 	let mut chip8_emulator = Emulator::new(&emu_config);
-	
-	let mut rom = File::open(&args.file_name).expect("Unable to open file");
-	let mut data_buffer = Vec::new();
-	rom.read_to_end(&mut data_buffer).unwrap();
-	chip8_emulator.load(&data_buffer);
+	if let Err(e) = chip8_emulator.load(&data_buffer) {
+		eprintln!("Unable to load {}: {}", rom_name, e);
+		std::process::exit(1);
+	}
+	if let Some(font_file) = &args.font_file {
+		let font_loaded = std::fs::read(font_file)
+			.map_err(|e| e.to_string())
+			.and_then(|data| chip8_emulator.load_font(&data).map_err(|e| e.to_string()));
+		if let Err(e) = font_loaded {
+			eprintln!("Unable to load font {}: {}", font_file, e);
+			std::process::exit(1);
+		}
+	}
 
 	
 	let audio_subsystem = sdl_context.audio().unwrap();
@@ -61,7 +148,38 @@ fn main() {
 	let timer_subsystem = sdl_context.timer().unwrap();
 
 	let mut save_state: Option<Emulator> = None;
+
+	// How many instructions from outside the variant have been reported
+	let mut reported_opcodes = 0;
 	
+	// The RGBA bytes of the screen, and the palette they were rendered with.
+	// The frame is only rendered again when the screen or the palette change
+	let mut frame = Vec::new();
+	let mut frame_palette = None;
+
+	// The emulator keeps its own 60 Hz clock, and runs the frames for the
+	// time that passed since the last loop, whatever the host frame rate is
+	let mut last_time = Instant::now();
+
+	// While paused, frames only run one at a time with frame advance. Slow
+	// motion divides the time that passes by 2 or 4
+	let mut paused = false;
+	let mut slow_motion = 1;
+
+	// Fast-forwarding runs args.fast_forward frames for every normal one, or
+	// as many as fit in a host frame when it is 0, and only draws the last
+	let mut fast_forward_held = false;
+	let mut fast_forward_toggled = false;
+	let fast_forward_status = match args.fast_forward {
+		0 => "FF".to_string(),
+		factor => format!("FF X{}", factor),
+	};
+
+	// The speed is measured once per second and shown in the window title
+	let mut measure_time = Instant::now();
+	let mut measure_count = 0;
+	let mut measured_ips = speed.get_ips();
+
 	let mut event_pump = sdl_context.event_pump().unwrap();
 	'running: loop {
 		let start: u64 = timer_subsystem.performance_counter();
@@ -74,7 +192,7 @@ fn main() {
 				Event::KeyDown { keycode: Some(Keycode::Backspace), .. } => {
 					// Reset the emulator
 					chip8_emulator.reset();
-					chip8_emulator.load(&data_buffer);
+					chip8_emulator.load(&data_buffer).expect("The ROM was loaded on startup");
 				},
 				Event::KeyDown { keycode: Some(Keycode::Right), .. } => {
 					// Change the palette
@@ -85,17 +203,57 @@ fn main() {
 					video_driver.move_palette_left();
 				},
 				Event::KeyDown { keycode: Some(Keycode::Up), .. } => {
-					// Increase the ticks per frame by increments of 5
-					ticks_per_frame += 5;
+					// Increase the speed by 5 ticks per frame
+					if let Speed::Ips(ips) = speed {
+						speed = Speed::Ips(ips + 5 * 60);
+						video_driver.show_message(&format!("{} IPS", speed.get_ips()));
+					}
 				},
 				Event::KeyDown { keycode: Some(Keycode::Down), .. } => {
-					// Decrease the ticks per frame by increments of 5
-					ticks_per_frame = if ticks_per_frame <= 5 {
-						5
-					} else {
-						ticks_per_frame - 5
+					// Decrease the speed by 5 ticks per frame
+					if let Speed::Ips(ips) = speed {
+						speed = Speed::Ips(ips.saturating_sub(5 * 60).max(5 * 60));
+						video_driver.show_message(&format!("{} IPS", speed.get_ips()));
+					}
+				},
+				Event::KeyDown { keycode: Some(Keycode::P), .. } => {
+					// Switch to the next speed preset
+					speed = speed.next_preset();
+					chip8_emulator.set_vip_timing(speed == Speed::Vip);
+					match speed {
+						Speed::Ips(ips) => video_driver.show_message(&format!("{} IPS", ips)),
+						Speed::Vip => video_driver.show_message("VIP"),
 					}
 				},
+				Event::KeyDown { keycode: Some(Keycode::Space), .. } => {
+					// Pause/Resume
+					paused = !paused;
+				},
+				Event::KeyDown { keycode: Some(Keycode::N), .. } => {
+					// Pause, and run a single frame
+					paused = true;
+					chip8_emulator.run_until_vblank(speed.get_ips() / 60);
+				},
+				Event::KeyDown { keycode: Some(Keycode::L), .. } => {
+					// Cycle the slow motion through full, half and quarter speed
+					slow_motion = match slow_motion {
+						1 => 2,
+						2 => 4,
+						_ => 1,
+					};
+					video_driver.show_message(&format!("X1/{}", slow_motion));
+				},
+				Event::KeyDown { keycode: Some(Keycode::Tab), .. } => {
+					// Fast-forward while the key is held
+					fast_forward_held = true;
+				},
+				Event::KeyUp { keycode: Some(Keycode::Tab), .. } => {
+					fast_forward_held = false;
+				},
+				Event::KeyDown { keycode: Some(Keycode::T), .. } => {
+					// Toggle fast-forwarding
+					fast_forward_toggled = !fast_forward_toggled;
+				},
 				Event::KeyDown { keycode: Some(Keycode::M), .. } => {
 					// Mute/Unmute
 					audio_driver.toggle_mute();
@@ -108,34 +266,111 @@ fn main() {
 					// If there is a save state, load it
 					if save_state.is_some() {
 						chip8_emulator = save_state.clone().unwrap();
+						chip8_emulator.set_vip_timing(speed == Speed::Vip);
+						frame_palette = None;
+					}
+				},
+				Event::KeyDown { keycode: Some(Keycode::G), .. } => {
+					// Export the ROM and the current settings as an Octo cartridge
+					let cartridge_name = get_cartridge_path(&rom_path);
+					let options = octo::OctoOptions::from_emuconfig(&emu_config, get_tickrate(speed, measured_ips), video_driver.get_current_palette());
+					match octo::save_cartridge(&cartridge_name, &data_buffer, &options, video_driver.get_current_palette(),
+											   &chip8_emulator.get_pixel_indices(), chip8_emulator.get_screen_width(), chip8_emulator.get_screen_height()) {
+						Ok(()) => println!("Saved cartridge to {}", cartridge_name),
+						Err(e) => eprintln!("{}", e),
+					}
+				},
+				Event::KeyDown { keycode: Some(Keycode::J), .. } => {
+					// Export the current settings as Octo options
+					let options_name = Path::new(&rom_path).with_extension("json").to_string_lossy().to_string();
+					let options = octo::OctoOptions::from_emuconfig(&emu_config, get_tickrate(speed, measured_ips), video_driver.get_current_palette());
+					match octo::save_options(&options_name, &options) {
+						Ok(()) => println!("Saved options to {}", options_name),
+						Err(e) => eprintln!("{}", e),
 					}
 				},
 				Event::KeyDown { keycode: Some(key), ..} => {
 					if let Some(k) = key2button(key) {
 						chip8_emulator.register_keypress(k, true);
 					}
+					if let Some(k) = key2button_2(key) {
+						chip8_emulator.register_keypress_2(k, true);
+					}
+				},
+				Event::Window { .. } => {
+					// The window may need to be drawn again after it was
+					// covered or resized
+					video_driver.force_redraw();
 				},
 				Event::KeyUp {keycode: Some(key), ..} => {
 					if let Some(k) = key2button(key) {
 						chip8_emulator.register_keypress(k, false);
 					}
+					if let Some(k) = key2button_2(key) {
+						chip8_emulator.register_keypress_2(k, false);
+					}
 				},
 				_ => ()
 			}
 		}
-		for i in 0..ticks_per_frame {
-			// The first tick on each frame is a "key" frame
-			// This is useful for display wait 
-			match i {
-				0 => chip8_emulator.tick(true),
-				_ => chip8_emulator.tick(false),
-			}				
+		let now = Instant::now();
+		let fast_forwarding = fast_forward_held || fast_forward_toggled;
+		let elapsed = (now - last_time) / slow_motion;
+		if paused {
+			// Frames only run with frame advance
+		} else if !fast_forwarding {
+			chip8_emulator.run_for(elapsed, speed.get_ips());
+		} else if args.fast_forward == 0 {
+			while now.elapsed() < FAST_FORWARD_BUDGET {
+				chip8_emulator.run_until_vblank(speed.get_ips() / 60);
+			}
+		} else {
+			for _ in 0..args.fast_forward {
+				chip8_emulator.run_for(elapsed, speed.get_ips());
+			}
 		}
+		last_time = now;
+
+		if paused {
+			video_driver.show_status(Some("PAUSE"));
+		} else if fast_forwarding {
+			video_driver.show_status(Some(&fast_forward_status));
+		} else {
+			video_driver.show_status(None);
+		}
+		let mute_fast_forward = fast_forwarding && args.fast_forward_audio == CLIFastForwardAudio::Mute;
+		audio_driver.set_silenced(paused || mute_fast_forward);
+
+		if measure_time.elapsed() >= Duration::from_secs(1) {
+			let count = chip8_emulator.get_instruction_count();
+			measured_ips = (count.saturating_sub(measure_count) as f64 / measure_time.elapsed().as_secs_f64()) as u32;
+			video_driver.show_speed(measured_ips);
+			measure_time = Instant::now();
+			measure_count = count;
+		}
+		// Loading a save state can go back to an earlier report
+		reported_opcodes = reported_opcodes.min(chip8_emulator.get_invalid_opcodes().len());
+		for (address, opcode) in chip8_emulator.get_invalid_opcodes().iter().skip(reported_opcodes) {
+			eprintln!("Warning: {:04X} at {:#05X} is not an instruction of the selected variant", opcode, address);
+		}
+		reported_opcodes = chip8_emulator.get_invalid_opcodes().len();
 
-		chip8_emulator.tick_timers();
 		audio_driver.handle_audio(chip8_emulator.beep);
 		
-		video_driver.draw_window(chip8_emulator.get_screen_buffers());
+		// The emulator renders the screen with the palette, at its own size
+		let palette = video_driver.get_palette_colors();
+		let changed = chip8_emulator.take_dirty().is_some() || frame_palette != Some(palette);
+		if changed {
+			frame.resize(chip8_emulator.get_render_buffer_size(1), 0);
+			chip8_emulator.render(&palette, 1, PixelFormat::Rgba, &mut frame)
+				.expect("The frame is resized to fit the screen");
+			frame_palette = Some(palette);
+		}
+		if chip8_emulator.is_mega_mode() {
+			video_driver.draw_mega_window(&frame, changed);
+		} else {
+			video_driver.draw_window(&frame, changed);
+		}
 
 		if chip8_emulator.get_sound_frequency() != audio_driver.frequency {
 			audio_driver.update_frequency(&audio_subsystem, chip8_emulator.get_sound_frequency());
@@ -145,15 +380,22 @@ fn main() {
 		if pattern_buffer_copy != audio_driver.pattern_buffer {
 			audio_driver.update_pattern_buffer(pattern_buffer_copy);
 		}
+
+		let mega_sound = chip8_emulator.get_mega_sound();
+		if mega_sound != audio_driver.mega_sound.as_ref() {
+			audio_driver.update_mega_sound(mega_sound);
+		}
 		
 		let end: u64 = timer_subsystem.performance_counter();
 		let seconds: f64 = (end - start) as f64 / timer_subsystem.performance_frequency() as f64;
 
-		// The amount of time left to ensure 60 fps (if vsync is on)
+		// The amount of time left to ensure 60 fps (if vsync is on). The
+		// emulation speed does not depend on it. Without the cap, the loop
+		// still sleeps when there is nothing to run until the next frame
 		let nominator = 1_000_000_000u64 / 60;
 		let denominator = (seconds * 1_000_000_000f64) as u64;
 		let time_delay = nominator.checked_sub(denominator);
-		if !args.fpscap_off {
+		if !args.fpscap_off || paused || chip8_emulator.is_idle() {
 			match time_delay {
 				Some(result) => spin_sleep::sleep(Duration::new(0, result as u32)),
 		
@@ -167,6 +409,35 @@ fn main() {
 		// let current_fps = 1.0 / seconds;
 		// println!("FPS: {}", current_fps);
 	}
+
+	if args.strict && !chip8_emulator.get_invalid_opcodes().is_empty() {
+		eprintln!("{} instruction(s) that do not exist on the selected variant were run:", chip8_emulator.get_invalid_opcodes().len());
+		for (address, opcode) in chip8_emulator.get_invalid_opcodes() {
+			eprintln!("  {:#05X}: {:04X}", address, opcode);
+		}
+	}
+}
+
+// The ticks per frame stored in Octo options. The VIP timing has no fixed
+// rate, so the measured one is used
+fn get_tickrate(speed: Speed, measured_ips: u32) -> u32 {
+	let ips = match speed {
+		Speed::Ips(ips) => ips,
+		Speed::Vip => measured_ips,
+	};
+	((ips + 30) / 60).max(1)
+}
+
+// Returns the file an exported cartridge is saved to: the ROM's name with a
+// .gif extension, unless the ROM already is a cartridge
+fn get_cartridge_path(file_name: &str) -> String {
+	let path = Path::new(file_name);
+	if octo::is_cartridge(file_name) {
+		let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+		path.with_file_name(format!("{}_export.gif", stem)).to_string_lossy().to_string()
+	} else {
+		path.with_extension("gif").to_string_lossy().to_string()
+	}
 }
 
 fn key2button(key: Keycode) -> Option<usize> {
@@ -191,5 +462,28 @@ fn key2button(key: Keycode) -> Option<usize> {
 	}
 }
 
+// The second keypad of the CHIP-8X is on the numpad
+fn key2button_2(key: Keycode) -> Option<usize> {
+	match key {
+		Keycode::Kp7 =>        Some(0x1),
+		Keycode::Kp8 =>        Some(0x2),
+		Keycode::Kp9 =>        Some(0x3),
+		Keycode::KpDivide =>   Some(0xC),
+		Keycode::Kp4 =>        Some(0x4),
+		Keycode::Kp5 =>        Some(0x5),
+		Keycode::Kp6 =>        Some(0x6),
+		Keycode::KpMultiply => Some(0xD),
+		Keycode::Kp1 =>        Some(0x7),
+		Keycode::Kp2 =>        Some(0x8),
+		Keycode::Kp3 =>        Some(0x9),
+		Keycode::KpMinus =>    Some(0xE),
+		Keycode::Kp0 =>        Some(0xA),
+		Keycode::KpPeriod =>   Some(0x0),
+		Keycode::KpEnter =>    Some(0xB),
+		Keycode::KpPlus =>     Some(0xF),
+		_ =>                   None,
+	}
+}
+
 
 
@@ -0,0 +1,483 @@
+// Support for Octo "cartridges": GIF images that carry a program together
+// with the options it should run with. The program is Octo source code, and
+// only sources that list bytes can be read, which covers the cartridges this
+// emulator exports but not most of the ones made with Octo.
+//
+// The payload is hidden in the color indices of the image. Every pixel holds
+// one nibble in the lower 4 bits of its index (the upper 4 bits pick the
+// color of the label), two consecutive pixels form a byte, and the frames of
+// the GIF are read one after the other. The first 4 bytes are the length of
+// the payload (big-endian), followed by a UTF-8 JSON object of the form
+// { "options": {...}, "program": "..." }.
+
+use std::borrow::Cow;
+use std::fs;
+use std::fs::File;
+
+use serde_json::Value;
+use serde_json::json;
+
+use chip8_core::EmuConfig;
+use chip8_core::FontStyle;
+use chip8_core::Variant;
+use sdl2::pixels::Color;
+
+use crate::video_driver::Palette;
+use crate::video_driver::hex_to_rgb;
+
+// The options Octo stores next to every program. The settings of the
+// emulation are None when a file leaves them out, so that they keep the
+// values of the command line
+#[derive(Clone)]
+pub struct OctoOptions {
+	pub tickrate: Option<u32>,
+	pub max_size: Option<usize>,
+	// The platform, stored in a key of our own ("crispVariant") that Octo
+	// ignores, as the maximum size cannot tell most of them apart
+	pub variant: Option<Variant>,
+
+	pub shift_quirks: Option<bool>,
+	pub load_store_quirks: Option<bool>,
+	pub vf_order_quirks: Option<bool>,
+	pub clip_quirks: Option<bool>,
+	pub jump_quirks: Option<bool>,
+	pub vblank_quirks: Option<bool>,
+	pub logic_quirks: Option<bool>,
+
+	pub font_style: Option<FontStyle>,
+
+	pub background_color: String,
+	pub fill_color: String,
+	pub fill_color2: String,
+	pub blend_color: String,
+	pub buzz_color: String,
+	pub quiet_color: String,
+}
+
+// A decoded cartridge: the ROM image and the options that came with it
+pub struct Cartridge {
+	pub rom: Vec<u8>,
+	pub options: OctoOptions,
+}
+
+impl Default for OctoOptions {
+	// The defaults Octo uses for a new program
+	fn default() -> Self {
+		OctoOptions {
+			tickrate: Some(20),
+			max_size: Some(3584),
+			variant: None,
+
+			shift_quirks: Some(false),
+			load_store_quirks: Some(false),
+			vf_order_quirks: Some(false),
+			clip_quirks: Some(false),
+			jump_quirks: Some(false),
+			vblank_quirks: Some(false),
+			logic_quirks: Some(false),
+
+			font_style: Some(FontStyle::Octo),
+
+			background_color: "#996600".to_string(),
+			fill_color: "#FFCC00".to_string(),
+			fill_color2: "#FF6600".to_string(),
+			blend_color: "#662200".to_string(),
+			buzz_color: "#FFAA00".to_string(),
+			quiet_color: "#000000".to_string(),
+		}
+	}
+}
+
+impl OctoOptions {
+	// Read the options from a JSON object. Missing settings are None, and
+	// missing colors keep Octo's defaults
+	pub fn from_json(json: &Value) -> Self {
+		let defaults = OctoOptions::default();
+		let get_bool = |key: &str| json.get(key).and_then(|value| value.as_bool());
+		let get_color = |key: &str, default: &String| {
+			json.get(key).and_then(|value| value.as_str()).map(|s| s.to_string()).unwrap_or(default.clone())
+		};
+		OctoOptions {
+			tickrate: json.get("tickrate").and_then(|value| value.as_u64()).map(|t| t as u32),
+			max_size: json.get("maxSize").and_then(|value| value.as_u64()).map(|s| s as usize),
+			variant: json.get("crispVariant").and_then(|value| value.as_str()).and_then(variant_from_name),
+
+			shift_quirks: get_bool("shiftQuirks"),
+			load_store_quirks: get_bool("loadStoreQuirks"),
+			vf_order_quirks: get_bool("vfOrderQuirks"),
+			clip_quirks: get_bool("clipQuirks"),
+			jump_quirks: get_bool("jumpQuirks"),
+			vblank_quirks: get_bool("vBlankQuirks"),
+			logic_quirks: get_bool("logicQuirks"),
+
+			font_style: json.get("fontStyle").and_then(|value| value.as_str()).and_then(font_from_name),
+
+			background_color: get_color("backgroundColor", &defaults.background_color),
+			fill_color: get_color("fillColor", &defaults.fill_color),
+			fill_color2: get_color("fillColor2", &defaults.fill_color2),
+			blend_color: get_color("blendColor", &defaults.blend_color),
+			buzz_color: get_color("buzzColor", &defaults.buzz_color),
+			quiet_color: get_color("quietColor", &defaults.quiet_color),
+		}
+	}
+
+	// Create the options that describe an emulation config and its settings
+	pub fn from_emuconfig(emu_config: &EmuConfig, ticks_per_frame: u32, palette: &Palette) -> Self {
+		let defaults = OctoOptions::default();
+		let max_size = match emu_config.variant {
+			Variant::Chip8 | Variant::Chip8HiRes | Variant::Chip10 | Variant::Chip8X | Variant::Chip48 => 3215,
+			Variant::SChip10 | Variant::SChip => 3583,
+			// Octo has no MEGA-CHIP mode, XO-CHIP is the closest
+			Variant::XOChip | Variant::MegaChip => 65024,
+		};
+		OctoOptions {
+			tickrate: Some(ticks_per_frame),
+			max_size: Some(max_size),
+			variant: Some(emu_config.variant),
+
+			shift_quirks: Some(emu_config.quirk_shifting),
+			load_store_quirks: Some(!emu_config.quirk_memory),
+			vf_order_quirks: Some(emu_config.quirk_vforder),
+			clip_quirks: Some(emu_config.quirk_clipping),
+			jump_quirks: Some(emu_config.quirk_jumping),
+			vblank_quirks: Some(emu_config.quirk_displaywait),
+			logic_quirks: Some(emu_config.quirk_vfreset),
+
+			font_style: Some(emu_config.font_style),
+
+			background_color: color_to_hex(palette.colors[0]),
+			fill_color: color_to_hex(palette.colors[1]),
+			fill_color2: color_to_hex(palette.colors[2]),
+			blend_color: color_to_hex(palette.colors[3]),
+			buzz_color: defaults.buzz_color,
+			quiet_color: defaults.quiet_color,
+		}
+	}
+
+	// Write the options as the JSON object Octo uses, leaving out the
+	// settings that are None
+	pub fn to_json(&self) -> Value {
+		let mut json = json!({
+			"tickrate": self.tickrate,
+			"maxSize": self.max_size,
+			"crispVariant": self.variant.map(variant_to_name),
+			"shiftQuirks": self.shift_quirks,
+			"loadStoreQuirks": self.load_store_quirks,
+			"vfOrderQuirks": self.vf_order_quirks,
+			"clipQuirks": self.clip_quirks,
+			"jumpQuirks": self.jump_quirks,
+			"vBlankQuirks": self.vblank_quirks,
+			"logicQuirks": self.logic_quirks,
+			"fontStyle": self.font_style.map(font_to_name),
+			"backgroundColor": self.background_color,
+			"fillColor": self.fill_color,
+			"fillColor2": self.fill_color2,
+			"blendColor": self.blend_color,
+			"buzzColor": self.buzz_color,
+			"quietColor": self.quiet_color,
+		});
+		if let Some(object) = json.as_object_mut() {
+			object.retain(|_, value| !value.is_null());
+		}
+		json
+	}
+
+	// Octo picks the platform through the maximum size of the program. The
+	// platform we stored ourselves comes first. None if the options have
+	// neither
+	pub fn get_variant(&self) -> Option<Variant> {
+		if let Some(variant) = self.variant {
+			return Some(variant);
+		}
+		self.max_size.map(|max_size| {
+			if max_size <= 3232 {
+				Variant::Chip8
+			} else if max_size == 3583 {
+				Variant::SChip
+			} else {
+				Variant::XOChip
+			}
+		})
+	}
+
+	// Apply the options on an emulation config. A platform in the options
+	// starts from the config of that platform, and the settings the options
+	// leave out keep their values
+	pub fn apply_to(&self, emu_config: &mut EmuConfig) {
+		if let Some(variant) = self.get_variant() {
+			*emu_config = EmuConfig::new(variant);
+		}
+		if let Some(logic_quirks) = self.logic_quirks {
+			emu_config.quirk_vfreset = logic_quirks;
+		}
+		if let Some(load_store_quirks) = self.load_store_quirks {
+			emu_config.quirk_memory = !load_store_quirks;
+			emu_config.quirk_memoryincrementbyx = false;
+		}
+		if let Some(vblank_quirks) = self.vblank_quirks {
+			emu_config.quirk_displaywait = vblank_quirks;
+		}
+		if let Some(clip_quirks) = self.clip_quirks {
+			emu_config.quirk_clipping = clip_quirks;
+		}
+		if let Some(shift_quirks) = self.shift_quirks {
+			emu_config.quirk_shifting = shift_quirks;
+		}
+		if let Some(jump_quirks) = self.jump_quirks {
+			emu_config.quirk_jumping = jump_quirks;
+		}
+		if let Some(vf_order_quirks) = self.vf_order_quirks {
+			emu_config.quirk_vforder = vf_order_quirks;
+		}
+		if let Some(font_style) = self.font_style {
+			emu_config.font_style = font_style;
+		}
+	}
+
+	// Returns a palette made from the 4 colors Octo uses for the bitplanes
+	pub fn get_palette(&self) -> Palette {
+		let mut colors = [Color::RGB(255, 0, 0); 16];
+		let octo_colors = [&self.background_color, &self.fill_color, &self.fill_color2, &self.blend_color];
+		for (i, hex) in octo_colors.iter().enumerate() {
+			let (r, g, b) = hex_to_rgb(hex).unwrap_or((0, 0, 0));
+			colors[i] = Color::RGB(r, g, b);
+		}
+		Palette {
+			colors
+		}
+	}
+}
+
+// Read Octo options from a JSON file
+pub fn load_options(file_name: &str) -> Result<OctoOptions, String> {
+	let text = fs::read_to_string(file_name).map_err(|e| format!("Unable to open {}: {}", file_name, e))?;
+	let json: Value = serde_json::from_str(&text).map_err(|e| format!("Invalid options file {}: {}", file_name, e))?;
+	if !json.is_object() {
+		return Err(format!("Invalid options file {}: expected a JSON object", file_name));
+	}
+	Ok(OctoOptions::from_json(&json))
+}
+
+// Write Octo options to a JSON file
+pub fn save_options(file_name: &str, options: &OctoOptions) -> Result<(), String> {
+	let text = serde_json::to_string_pretty(&options.to_json()).unwrap();
+	fs::write(file_name, text).map_err(|e| format!("Unable to write {}: {}", file_name, e))
+}
+
+// Returns true if the file looks like an Octo cartridge
+pub fn is_cartridge(file_name: &str) -> bool {
+	file_name.to_lowercase().ends_with(".gif")
+}
+
+// Decode an Octo cartridge from the contents of a GIF file
+pub fn load_cartridge(data: &[u8]) -> Result<Cartridge, String> {
+	let mut decode_options = gif::DecodeOptions::new();
+	decode_options.set_color_output(gif::ColorOutput::Indexed);
+	let mut decoder = decode_options.read_info(data).map_err(|e| format!("Invalid GIF file: {}", e))?;
+
+	// Every pixel holds a nibble, so join the frames and pack them into bytes
+	let mut nibbles: Vec<u8> = Vec::new();
+	while let Some(frame) = decoder.read_next_frame().map_err(|e| format!("Invalid GIF file: {}", e))? {
+		nibbles.extend(frame.buffer.iter().map(|index| index & 0xF));
+	}
+	let bytes: Vec<u8> = nibbles.chunks_exact(2).map(|pair| (pair[0] << 4) | pair[1]).collect();
+
+	if bytes.len() < 4 {
+		return Err("Not an Octo cartridge: no payload found".to_string());
+	}
+	let size = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+	if bytes.len() < 4 + size {
+		return Err("Not an Octo cartridge: the payload is truncated".to_string());
+	}
+	let json: Value = serde_json::from_slice(&bytes[4..4 + size])
+		.map_err(|e| format!("Not an Octo cartridge: {}", e))?;
+
+	let options = OctoOptions::from_json(json.get("options").unwrap_or(&Value::Null));
+	let program = json.get("program").and_then(|value| value.as_str())
+		.ok_or("The cartridge does not contain a program")?;
+	let rom = parse_byte_listing(program)?;
+
+	Ok(Cartridge {
+		rom,
+		options,
+	})
+}
+
+// Octo stores the program as the Octo source it was written in, which we
+// cannot assemble. Only a source that lists nothing but bytes (optionally
+// after ": main") maps directly to a ROM image. Such listings are what
+// to_byte_listing writes, so this reads back the cartridges we export
+pub fn parse_byte_listing(source: &str) -> Result<Vec<u8>, String> {
+	let mut rom = Vec::new();
+	for line in source.lines() {
+		// Everything after a # is a comment
+		let line = line.split('#').next().unwrap_or("");
+		let mut tokens = line.split_whitespace();
+		while let Some(token) = tokens.next() {
+			if token == ":" {
+				// Skip the label name
+				tokens.next();
+				continue;
+			}
+			let value = if let Some(hex) = token.strip_prefix("0x").or(token.strip_prefix("0X")) {
+				i64::from_str_radix(hex, 16)
+			} else if let Some(binary) = token.strip_prefix("0b").or(token.strip_prefix("0B")) {
+				i64::from_str_radix(binary, 2)
+			} else {
+				token.parse::<i64>()
+			};
+			match value {
+				Ok(v) if (-128..=255).contains(&v) => rom.push(v as u8),
+				_ => return Err(format!("The cartridge contains Octo source code, which cannot be assembled: only cartridges that list the bytes of the program, like the ones this emulator exports, can be opened (found '{}')", token)),
+			}
+		}
+	}
+	Ok(rom)
+}
+
+// Write a ROM as Octo source that consists of nothing but its bytes
+pub fn to_byte_listing(rom: &[u8]) -> String {
+	let mut source = ": main\n".to_string();
+	for line in rom.chunks(16) {
+		let bytes: Vec<String> = line.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+		source += &bytes.join(" ");
+		source += "\n";
+	}
+	source
+}
+
+// Size of the cartridge image
+const CARTRIDGE_WIDTH: usize = 160;
+const CARTRIDGE_HEIGHT: usize = 128;
+
+// Write an Octo cartridge. The label shows the given screen (the color index
+// of every pixel, as the emulator draws it) with the colors of the palette
+pub fn save_cartridge(file_name: &str, rom: &[u8], options: &OctoOptions, palette: &Palette,
+					  pixels: &[usize], screen_width: usize, screen_height: usize) -> Result<(), String> {
+	let payload = json!({
+		"options": options.to_json(),
+		"program": to_byte_listing(rom),
+	}).to_string();
+
+	// Prefix the payload with its size and split it into nibbles
+	let mut bytes = (payload.len() as u32).to_be_bytes().to_vec();
+	bytes.extend(payload.as_bytes());
+	let nibbles: Vec<u8> = bytes.iter().flat_map(|byte| [byte >> 4, byte & 0xF]).collect();
+
+	let label = draw_label(pixels, screen_width, screen_height);
+
+	// Each color of the palette is repeated 16 times, so that the lower
+	// nibble of an index does not change what the label looks like
+	let mut gif_palette = Vec::new();
+	for color in palette.colors.iter() {
+		for _ in 0..16 {
+			gif_palette.extend([color.r, color.g, color.b]);
+		}
+	}
+
+	let file = File::create(file_name).map_err(|e| format!("Unable to create {}: {}", file_name, e))?;
+	let mut encoder = gif::Encoder::new(file, CARTRIDGE_WIDTH as u16, CARTRIDGE_HEIGHT as u16, &gif_palette)
+		.map_err(|e| format!("Unable to write the cartridge: {}", e))?;
+
+	let frame_size = CARTRIDGE_WIDTH * CARTRIDGE_HEIGHT;
+	for frame_nibbles in nibbles.chunks(frame_size) {
+		let mut indices = vec![0u8; frame_size];
+		for (i, index) in indices.iter_mut().enumerate() {
+			let nibble = frame_nibbles.get(i).copied().unwrap_or(0);
+			*index = (label[i] << 4) | nibble;
+		}
+		let frame = gif::Frame {
+			width: CARTRIDGE_WIDTH as u16,
+			height: CARTRIDGE_HEIGHT as u16,
+			buffer: Cow::Borrowed(&indices),
+			..gif::Frame::default()
+		};
+		encoder.write_frame(&frame).map_err(|e| format!("Unable to write the cartridge: {}", e))?;
+	}
+	Ok(())
+}
+
+// Draw the label of a cartridge as palette indices: a frame in the blend
+// color with the screen in the middle, scaled to fit a 128x64 window
+fn draw_label(pixels: &[usize], screen_width: usize, screen_height: usize) -> Vec<u8> {
+	let mut label = vec![3u8; CARTRIDGE_WIDTH * CARTRIDGE_HEIGHT];
+	let scale = (128 / screen_width).min(64 / screen_height).max(1);
+	let left = (CARTRIDGE_WIDTH - screen_width * scale) / 2;
+	let top = 24;
+
+	for y in 0..screen_height * scale {
+		for x in 0..screen_width * scale {
+			let index = (x / scale) + screen_width * (y / scale);
+			label[(top + y) * CARTRIDGE_WIDTH + left + x] = pixels[index] as u8;
+		}
+	}
+
+	// A stripe in the first fill color under the screen
+	let stripe_top = top + screen_height * scale + 12;
+	for y in stripe_top..stripe_top + 8 {
+		for x in left..CARTRIDGE_WIDTH - left {
+			label[y * CARTRIDGE_WIDTH + x] = 1;
+		}
+	}
+	label
+}
+
+// The names of the platforms in the crispVariant key, the same as the ones
+// of --variant
+fn variant_from_name(name: &str) -> Option<Variant> {
+	match name {
+		"chip8" => Some(Variant::Chip8),
+		"chip8-hires" => Some(Variant::Chip8HiRes),
+		"chip10" => Some(Variant::Chip10),
+		"chip8x" => Some(Variant::Chip8X),
+		"chip48" => Some(Variant::Chip48),
+		"schip10" => Some(Variant::SChip10),
+		"schip" => Some(Variant::SChip),
+		"xo-chip" => Some(Variant::XOChip),
+		"megachip" => Some(Variant::MegaChip),
+		_ => None,
+	}
+}
+
+fn variant_to_name(variant: Variant) -> &'static str {
+	match variant {
+		Variant::Chip8 => "chip8",
+		Variant::Chip8HiRes => "chip8-hires",
+		Variant::Chip10 => "chip10",
+		Variant::Chip8X => "chip8x",
+		Variant::Chip48 => "chip48",
+		Variant::SChip10 => "schip10",
+		Variant::SChip => "schip",
+		Variant::XOChip => "xo-chip",
+		Variant::MegaChip => "megachip",
+	}
+}
+
+// Octo's names for its fonts
+fn font_from_name(name: &str) -> Option<FontStyle> {
+	match name {
+		"octo" => Some(FontStyle::Octo),
+		"vip" => Some(FontStyle::Vip),
+		"dream6800" => Some(FontStyle::Dream6800),
+		"eti660" => Some(FontStyle::Eti660),
+		"schip" => Some(FontStyle::SChip11),
+		"fish" => Some(FontStyle::FishNChips),
+		_ => None,
+	}
+}
+
+fn font_to_name(font_style: FontStyle) -> &'static str {
+	match font_style {
+		FontStyle::Octo => "octo",
+		FontStyle::Vip => "vip",
+		FontStyle::Dream6800 => "dream6800",
+		FontStyle::Eti660 => "eti660",
+		FontStyle::SChip10 | FontStyle::SChip11 => "schip",
+		FontStyle::FishNChips => "fish",
+	}
+}
+
+// Returns the #RRGGBB hex code of a color
+fn color_to_hex(color: Color) -> String {
+	format!("#{:02X}{:02X}{:02X}", color.r, color.g, color.b)
+}
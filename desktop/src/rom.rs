@@ -0,0 +1,54 @@
+// Reading ROMs from plain files or from entries of .zip archives
+
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+
+use zip::ZipArchive;
+
+// Returns true if the file is a .zip archive
+pub fn is_archive(file_name: &str) -> bool {
+	file_name.to_lowercase().ends_with(".zip")
+}
+
+// Returns the names of all files inside a .zip archive
+pub fn list_archive(file_name: &str) -> Result<Vec<String>, String> {
+	let archive = open_archive(file_name)?;
+	let mut names: Vec<String> = archive.file_names()
+		.filter(|name| !name.ends_with('/'))
+		.map(|name| name.to_string())
+		.collect();
+	names.sort();
+	Ok(names)
+}
+
+// Read a ROM. For archives, the entry with the given name is read. If no
+// name is given, the archive must contain exactly one file. Returns the data
+// along with the name of the file it came from
+pub fn read_rom(file_name: &str, entry: Option<&str>) -> Result<(String, Vec<u8>), String> {
+	if !is_archive(file_name) {
+		let data = fs::read(file_name).map_err(|e| format!("Unable to open {}: {}", file_name, e))?;
+		return Ok((file_name.to_string(), data));
+	}
+
+	let entries = list_archive(file_name)?;
+	let entry_name = match entry {
+		Some(name) => name.to_string(),
+		None if entries.len() == 1 => entries[0].clone(),
+		None => {
+			return Err(format!("{} contains {} files, pick one with --zip-entry:\n{}", file_name, entries.len(), entries.join("\n")));
+		}
+	};
+
+	let mut archive = open_archive(file_name)?;
+	let mut zip_file = archive.by_name(&entry_name)
+		.map_err(|_| format!("{} does not contain {}, available files:\n{}", file_name, entry_name, entries.join("\n")))?;
+	let mut data = Vec::new();
+	zip_file.read_to_end(&mut data).map_err(|e| format!("Unable to read {} from {}: {}", entry_name, file_name, e))?;
+	Ok((entry_name, data))
+}
+
+fn open_archive(file_name: &str) -> Result<ZipArchive<File>, String> {
+	let file = File::open(file_name).map_err(|e| format!("Unable to open {}: {}", file_name, e))?;
+	ZipArchive::new(file).map_err(|e| format!("Invalid archive {}: {}", file_name, e))
+}
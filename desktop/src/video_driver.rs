@@ -1,10 +1,45 @@
+use std::time::{Duration, Instant};
+
 use config::Config;
 
+use chip8_core::{MEGA_SCREEN_WIDTH, MEGA_SCREEN_HEIGHT};
+
 use sdl2::VideoSubsystem;
 use sdl2::pixels::Color;
 use sdl2::rect::Rect;
-use sdl2::render::Canvas;
-use sdl2::video::Window;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::{Canvas, Texture, TextureCreator};
+use sdl2::video::{Window, WindowContext};
+
+const WINDOW_TITLE: &str = "Crisp: A CHIP-8, SUPER-CHIP, XO-CHIP, and MEGA-CHIP Emulator";
+
+// How long a message stays on the screen
+const MESSAGE_DURATION: Duration = Duration::from_secs(2);
+
+// A 3x5 font for the messages drawn over the screen, one row per byte
+const MESSAGE_FONT: [(char, [u8; 5]); 21] = [
+	('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+	('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+	('2', [0b111, 0b001, 0b111, 0b100, 0b111]),
+	('3', [0b111, 0b001, 0b111, 0b001, 0b111]),
+	('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+	('5', [0b111, 0b100, 0b111, 0b001, 0b111]),
+	('6', [0b111, 0b100, 0b111, 0b101, 0b111]),
+	('7', [0b111, 0b001, 0b010, 0b010, 0b010]),
+	('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+	('9', [0b111, 0b101, 0b111, 0b001, 0b111]),
+	('A', [0b010, 0b101, 0b111, 0b101, 0b101]),
+	('E', [0b111, 0b100, 0b110, 0b100, 0b111]),
+	('F', [0b111, 0b100, 0b110, 0b100, 0b100]),
+	('I', [0b111, 0b010, 0b010, 0b010, 0b111]),
+	('P', [0b110, 0b101, 0b110, 0b100, 0b100]),
+	('S', [0b011, 0b100, 0b010, 0b001, 0b110]),
+	('U', [0b101, 0b101, 0b101, 0b101, 0b111]),
+	('V', [0b101, 0b101, 0b101, 0b101, 0b010]),
+	('X', [0b101, 0b101, 0b010, 0b101, 0b101]),
+	('/', [0b001, 0b001, 0b010, 0b100, 0b100]),
+	(' ', [0b000, 0b000, 0b000, 0b000, 0b000]),
+];
 
 #[derive(Clone)]
 pub struct Palette {
@@ -15,62 +50,192 @@ pub struct Palette {
 // Holds all information needed for drawing to the screen
 pub struct VideoDriver {
 	screen_width: usize,
-	_screen_height: usize,
+	screen_height: usize,
 	canvas: Canvas<Window>,
+	// The screen is drawn to a texture the size of the emulated screen, and
+	// the renderer scales it to the window
+	texture_creator: TextureCreator<WindowContext>,
+	texture: Texture,
+	mega_texture: Option<Texture>,
+	// The message of the last frame that was drawn, and whether the next
+	// frame has to be drawn even if it did not change
+	last_message: Option<String>,
+	redraw: bool,
 
 	palettes: Vec<Palette>,
 	current_palette: usize,
 	scale: u32,
+
+	// A message drawn over the screen, and when it was shown
+	message: Option<(String, Instant)>,
+	// A message that stays on the screen while there is no other one
+	status: Option<String>,
 }
 
 impl VideoDriver {
 	pub fn new(video_subsystem: &VideoSubsystem, s_width: u32, s_height: u32, given_palettes: Vec<Palette>, given_scale: u32) -> Self {
 		let new_window_width = (s_width as u32) * given_scale;
 		let new_window_height = (s_height as u32) * given_scale;
-		let window = video_subsystem.window("Crisp: A CHIP-8, SUPER-CHIP, and XO-CHIP Emulator", new_window_width, new_window_height).position_centered().opengl().build().unwrap();
+		let window = video_subsystem.window(WINDOW_TITLE, new_window_width, new_window_height).position_centered().opengl().build().unwrap();
 		let mut new_canvas = window.into_canvas().present_vsync().build().unwrap();
 		
 		new_canvas.clear();
 		new_canvas.present();
+		let texture_creator = new_canvas.texture_creator();
+		let texture = texture_creator.create_texture_streaming(PixelFormatEnum::RGBA32, s_width, s_height).unwrap();
 		VideoDriver {
 			canvas: new_canvas,
+			texture_creator,
+			texture,
+			mega_texture: None,
+			last_message: None,
+			redraw: true,
+
 			screen_width: s_width as usize,
-			_screen_height: s_height as usize,
+			screen_height: s_height as usize,
 
 			palettes: given_palettes,
 			current_palette: 0,
 			scale: given_scale,
+
+			message: None,
+			status: None,
 		}
 	}
 
-	// Draw using the 2 screen buffer and the selected palette 
-	pub fn draw_window(&mut self, buffers: &Vec<Vec<bool>> ) {
-		self.canvas.set_draw_color(self.palettes[0].colors[0]);
-		self.canvas.clear();
+	// Draw the screen, rendered as RGBA bytes by the emulator. changed tells
+	// if it was rendered again since the last call
+	pub fn draw_window(&mut self, frame: &[u8], changed: bool) {
+		let logical_width = self.screen_width as u32 * self.scale;
+		let logical_height = self.screen_height as u32 * self.scale;
+		let background = self.get_current_palette().colors[0];
+		self.draw_frame(frame, changed, false, (logical_width, logical_height), background);
+	}
 
-		for index in 0..buffers[0].len() {
-			let x = (index % self.screen_width) as u32;
-			let y = (index / self.screen_width) as u32;
-			let rect = Rect::new((x * self.scale) as i32, (y * self.scale) as i32, self.scale, self.scale);
-			let pixel_value = self.get_pixel_value(buffers, index);
-			self.canvas.set_draw_color(self.palettes[self.current_palette].colors[pixel_value]);
-			self.canvas.fill_rect(rect).unwrap();
+	// Make the window fit the 4:3 screen of the MEGA-CHIP mode. The regular
+	// screen is then drawn letterboxed
+	pub fn enable_mega_screen(&mut self) {
+		let window_width = self.screen_width as u32 * self.scale;
+		let window_height = window_width * MEGA_SCREEN_HEIGHT as u32 / MEGA_SCREEN_WIDTH as u32;
+		self.canvas.window_mut().set_size(window_width, window_height).unwrap();
+		self.mega_texture = Some(self.texture_creator.create_texture_streaming(PixelFormatEnum::RGBA32,
+			MEGA_SCREEN_WIDTH as u32, MEGA_SCREEN_HEIGHT as u32).unwrap());
+	}
+
+	// Draw the MEGA-CHIP screen, rendered as RGBA bytes by the emulator
+	pub fn draw_mega_window(&mut self, frame: &[u8], changed: bool) {
+		let logical_size = (MEGA_SCREEN_WIDTH as u32, MEGA_SCREEN_HEIGHT as u32);
+		self.draw_frame(frame, changed, true, logical_size, Color::RGB(0, 0, 0));
+	}
+
+	// Draw the next frame from the next call on, even if it did not change
+	pub fn force_redraw(&mut self) {
+		self.redraw = true;
+	}
+
+	// Upload the RGBA bytes of a frame to its texture, and let the renderer
+	// scale it to the window. Frames that did not change, with the same
+	// message over them, are not drawn again
+	fn draw_frame(&mut self, frame: &[u8], changed: bool, mega: bool, logical_size: (u32, u32), background: Color) {
+		self.expire_message();
+		let message = self.get_message();
+		if !changed && !self.redraw && message == self.last_message {
+			return;
 		}
 
+		let texture = if mega {
+			self.mega_texture.as_mut().expect("enable_mega_screen creates the MEGA-CHIP texture")
+		} else {
+			&mut self.texture
+		};
+		let width = texture.query().width as usize;
+		texture.update(None, frame, width * 4).unwrap();
+
+		let (logical_width, logical_height) = logical_size;
+		self.canvas.set_logical_size(logical_width, logical_height).unwrap();
+		self.canvas.set_draw_color(background);
+		self.canvas.clear();
+		let texture = if mega { self.mega_texture.as_ref().unwrap() } else { &self.texture };
+		self.canvas.copy(texture, None, None).unwrap();
+		self.draw_message();
 		self.canvas.present();
+
+		self.last_message = message;
+		self.redraw = false;
+	}
+
+	// Show a message over the screen for a while. Only digits and the letters
+	// of MESSAGE_FONT are drawn
+	pub fn show_message(&mut self, text: &str) {
+		self.message = Some((text.to_uppercase(), Instant::now()));
+	}
+
+	// Show a message over the screen until it is set to None
+	pub fn show_status(&mut self, text: Option<&str>) {
+		self.status = text.map(|text| text.to_uppercase());
 	}
 
-	pub fn get_pixel_value(&self, buffers: &Vec<Vec<bool>>, index: usize) -> usize {
-		let mut pixel_value = 0;
-		for i in 0..buffers.len() {
-			let bit = buffers[i][index];
-			if bit {
-				pixel_value += 1 << i;
+	// Show the measured speed in the window title
+	pub fn show_speed(&mut self, ips: u32) {
+		let title = format!("{} ({} IPS)", WINDOW_TITLE, ips);
+		self.canvas.window_mut().set_title(&title).unwrap();
+	}
+
+	// Forget the message once it was shown for long enough
+	fn expire_message(&mut self) {
+		if matches!(&self.message, Some((_, shown)) if shown.elapsed() > MESSAGE_DURATION) {
+			self.message = None;
+		}
+	}
+
+	// The message to draw, if any: the last one shown, or the status
+	fn get_message(&self) -> Option<String> {
+		self.message.as_ref().map(|(text, _)| text).or(self.status.as_ref()).cloned()
+	}
+
+	// Draw the message (or the status) in the top left corner, on a box of
+	// the background color
+	fn draw_message(&mut self) {
+		let Some(text) = self.get_message() else {
+			return;
+		};
+		// A font pixel is 1/128 of the width of the screen
+		let (logical_width, _) = self.canvas.logical_size();
+		let size = (logical_width / 128).max(1);
+		let colors = self.palettes[self.current_palette].colors;
+		let chars = text.chars().count() as u32;
+		self.canvas.set_draw_color(colors[0]);
+		self.canvas.fill_rect(Rect::new(0, 0, (chars * 4 + 1) * size, 7 * size)).unwrap();
+		self.canvas.set_draw_color(colors[1]);
+		for (i, c) in text.chars().enumerate() {
+			let Some((_, rows)) = MESSAGE_FONT.iter().find(|(font_char, _)| *font_char == c) else {
+				continue;
+			};
+			for (y, row) in rows.iter().enumerate() {
+				for x in 0..3 {
+					if row & (0b100 >> x) != 0 {
+						let pixel_x = (i as u32 * 4 + 1 + x) * size;
+						let pixel_y = (y as u32 + 1) * size;
+						self.canvas.fill_rect(Rect::new(pixel_x as i32, pixel_y as i32, size, size)).unwrap();
+					}
+				}
 			}
 		}
-		return pixel_value as usize;
 	}
-	
+
+	// Returns the palette used at the moment
+	pub fn get_current_palette(&self) -> &Palette {
+		&self.palettes[self.current_palette]
+	}
+
+	// Returns the colors of the palette used at the moment as 0xRRGGBB, the
+	// way the emulator renders with them
+	pub fn get_palette_colors(&self) -> [u32; 16] {
+		self.get_current_palette().colors.map(|color| {
+			((color.r as u32) << 16) | ((color.g as u32) << 8) | color.b as u32
+		})
+	}
+
 	// Rotate the selected palette one spot to the right
 	pub fn move_palette_right(&mut self) {
 		self.current_palette = (self.current_palette + 1) % self.palettes.len()
@@ -87,6 +252,25 @@ impl VideoDriver {
 	}
 }
 
+// The colors of the CHIP-8X color board, in the order of
+// Emulator::get_color_indices: 4 background colors, then 8 foreground colors
+pub fn get_chip8x_palette() -> Palette {
+	let hex_colors = [
+		"#000080", "#000000", "#008000", "#800000",
+		"#000000", "#000000", "#000000", "#000000",
+		"#000000", "#FF0000", "#0000FF", "#FF00FF",
+		"#00FF00", "#FFFF00", "#00FFFF", "#FFFFFF",
+	];
+	let mut colors = [Color::RGB(0, 0, 0); 16];
+	for (color, hex) in colors.iter_mut().zip(hex_colors) {
+		let (r, g, b) = hex_to_rgb(hex).unwrap();
+		*color = Color::RGB(r, g, b);
+	}
+	Palette {
+		colors
+	}
+}
+
 // Get all palettes from config.toml. The first one is the selected one or
 // the default one
 pub fn get_all_palettes() -> Vec<Palette> {
@@ -235,7 +419,7 @@ fn get_palette_from_config(config: &Config, name: &str) -> Palette {
 }
 
 // Returns a simple #XYZABC hex code to 3 integer values
-fn hex_to_rgb(hex: &str) -> Option<(u8, u8, u8)> {
+pub fn hex_to_rgb(hex: &str) -> Option<(u8, u8, u8)> {
 	if hex.len() != 7 {
 		return None;
 	}